@@ -35,11 +35,11 @@ use envars::cmdargs::CmdArgs;
 use std::process;
 
 fn main() {
-    let mut args = CmdArgs::from_args();
-    match envars::start(&mut args) {
+    let (global, mut args) = CmdArgs::from_args();
+    match envars::start(&global, &mut args) {
         Err(error) => {
-            println!("{}", error);
-            process::exit(1);
+            eprintln!("{}", error);
+            process::exit(error.exit_code());
         }
         Ok(_) => process::exit(0),
     }
@@ -20,24 +20,156 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use dump::DumpFormat;
 use envset::EnvSetName;
+use shell::Shell;
 use std::env;
+use std::path::PathBuf;
 use std::process::Command;
+use storage;
+use storage::Storage;
 
 pub enum CmdArgs {
+    Copy(CopyArgs),
+    Decrypt(EnvSetName),
+    Delete(DeleteArgs),
+    Dump(DumpArgs),
     Edit(EnvSetName),
+    Encrypt(EncryptArgs),
+    Exec(RunArgs),
+    Export(ExportArgs),
+    Get(GetArgs),
+    Import(ImportArgs),
+    Lint(LintArgs),
     List,
-    New(EnvSetName),
-    Run(EnvSetName, Command),
+    Move(CopyArgs),
+    New(NewArgs),
+    Run(RunArgs),
+    Set(SetArgs),
+    Show(ShowArgs),
+    Unset(UnsetArgs),
     Help,
 }
 
+/// モードに依らない引数。モード名より前に指定する。
+pub struct GlobalArgs {
+    /// `--config-dir`で指定された、`EnvSet`ファイルを探すディレクトリ。
+    pub config_dirs: Vec<PathBuf>,
+}
+
+/// `lint`モードの引数。
+pub struct LintArgs {
+    /// 検査する`EnvSet`。空の場合は全ての`EnvSet`を検査する。
+    pub env_sets: Vec<EnvSetName>,
+    /// 結果をJSON形式で出力する。
+    pub json: bool,
+}
+
+/// `new`モードの引数。
+pub struct NewArgs {
+    pub env_set: EnvSetName,
+    /// 新しい`EnvSet`ファイルの保存形式。
+    pub storage: &'static Storage,
+}
+
+/// `run`モード及び`exec`モードの引数。
+pub struct RunArgs {
+    /// 左から順に重ね合わせる`EnvSet`。後のものほど優先される。
+    pub env_sets: Vec<EnvSetName>,
+    /// どの`EnvSet`の値が採用されたかを表示する。
+    pub verbose: bool,
+    /// 親プロセスの環境変数を引き継がず、`EnvSet`の変数と`keep`の変数だけを渡す。
+    pub clean: bool,
+    /// `clean`の時に既定の変数に加えて引き継ぐ環境変数名。
+    pub keep: Vec<String>,
+    pub cmd: Command,
+}
+
+/// `cp`モード及び`mv`モードの引数。
+pub struct CopyArgs {
+    pub src: EnvSetName,
+    pub dst: EnvSetName,
+    /// `dst`が既にある場合に上書きする。
+    pub force: bool,
+}
+
+/// `delete`モードの引数。
+pub struct DeleteArgs {
+    pub env_sets: Vec<EnvSetName>,
+    /// 確認せずに削除する。
+    pub yes: bool,
+    /// 削除する代わりに設定ディレクトリの`.trash`に移動する。
+    pub trash: bool,
+}
+
+/// `dump`モードの引数。
+pub struct DumpArgs {
+    pub env_set: EnvSetName,
+    pub format: DumpFormat,
+}
+
+/// `encrypt`モードの引数。
+pub struct EncryptArgs {
+    pub env_set: EnvSetName,
+    /// 暗号化する変数名。`None`の場合はファイル全体を暗号化する。
+    pub key: Option<String>,
+}
+
+/// `export`モードの引数。
+pub struct ExportArgs {
+    pub env_set: EnvSetName,
+    pub shell: Shell,
+}
+
+/// `get`モードの引数。
+pub struct GetArgs {
+    pub env_set: EnvSetName,
+    pub key: String,
+}
+
+/// `import`モードの引数。
+pub struct ImportArgs {
+    pub env_set: EnvSetName,
+    /// 読み込むdotenv形式のファイル。
+    pub from: PathBuf,
+}
+
+/// `set`モードの引数。
+pub struct SetArgs {
+    pub env_set: EnvSetName,
+    /// `KEY=VALUE`を分けた組。
+    pub vars: Vec<(String, String)>,
+}
+
+/// `show`モードの引数。
+pub struct ShowArgs {
+    pub env_set: EnvSetName,
+    /// 値を伏せて表示する。
+    pub mask: bool,
+}
+
+/// `unset`モードの引数。
+pub struct UnsetArgs {
+    pub env_set: EnvSetName,
+    pub keys: Vec<String>,
+}
+
 impl CmdArgs {
-    pub fn from_args() -> CmdArgs {
+    pub fn from_args() -> (GlobalArgs, CmdArgs) {
         let mut args = env::args().skip(1);
-        match args.next().and_then(|mode| Self::parse_args(&mode, &mut args)) {
-            Some(mode) => mode,
-            None => CmdArgs::Help,
+        let mut global = GlobalArgs { config_dirs: Vec::new() };
+        let mut mode = args.next();
+        while mode.as_ref().map_or(false, |arg| arg == "--config-dir") {
+            match args.next() {
+                Some(dir) => global.config_dirs.push(PathBuf::from(dir)),
+                None => return (global, CmdArgs::Help),
+            }
+            mode = args.next();
+        }
+
+        match mode.and_then(|mode| Self::parse_args(&mode, &mut args)) {
+            Some(mode) => (global, mode),
+            None => (global, CmdArgs::Help),
         }
     }
 
@@ -45,39 +177,427 @@ impl CmdArgs {
         where I: Iterator<Item = String>
     {
         match mode {
+            "cp" | "copy" => Self::parse_as_copy(args).map(CmdArgs::Copy),
+            "decrypt" => Self::parse_as_edit(args).map(CmdArgs::Decrypt),
+            "delete" | "rm" => Self::parse_as_delete(args).map(CmdArgs::Delete),
+            "dump" => Self::parse_as_dump(args).map(CmdArgs::Dump),
             "edit" => Self::parse_as_edit(args).map(CmdArgs::Edit),
+            "encrypt" => Self::parse_as_encrypt(args).map(CmdArgs::Encrypt),
+            "exec" => Self::parse_as_run(args).map(CmdArgs::Exec),
+            "export" => Self::parse_as_export(args).map(CmdArgs::Export),
+            "get" => Self::parse_as_get(args).map(CmdArgs::Get),
+            "import" => Self::parse_as_import(args).map(CmdArgs::Import),
+            "lint" => Self::parse_as_lint(args).map(CmdArgs::Lint),
             "list" => Some(CmdArgs::List),
+            "mv" | "rename" => Self::parse_as_copy(args).map(CmdArgs::Move),
             "new" => Self::parse_as_new(args).map(CmdArgs::New),
-            "run" => Self::parse_as_run(args).map(|(env_set, cmd)| CmdArgs::Run(env_set, cmd)),
+            "run" => Self::parse_as_run(args).map(CmdArgs::Run),
+            "set" => Self::parse_as_set(args).map(CmdArgs::Set),
+            "show" => Self::parse_as_show(args).map(CmdArgs::Show),
+            "unset" => Self::parse_as_unset(args).map(CmdArgs::Unset),
             _ => Some(CmdArgs::Help),
         }
     }
 
+    fn parse_as_copy<I>(args: &mut I) -> Option<CopyArgs>
+        where I: Iterator<Item = String>
+    {
+        let mut names = Vec::new();
+        let mut force = false;
+        for arg in args {
+            match arg.as_str() {
+                "-F" | "--force" => force = true,
+                _ => {
+                    match EnvSetName::new(&arg) {
+                        Some(name) => names.push(name),
+                        None => return None,
+                    }
+                }
+            }
+        }
+        if names.len() != 2 {
+            return None;
+        }
+        let dst = names.pop().unwrap();
+        let src = names.pop().unwrap();
+        Some(CopyArgs {
+            src: src,
+            dst: dst,
+            force: force,
+        })
+    }
+
+    fn parse_as_delete<I>(args: &mut I) -> Option<DeleteArgs>
+        where I: Iterator<Item = String>
+    {
+        let mut env_sets = Vec::new();
+        let mut yes = false;
+        let mut trash = false;
+        for arg in args {
+            match arg.as_str() {
+                "-y" | "--yes" => yes = true,
+                "--trash" => trash = true,
+                _ => {
+                    match EnvSetName::new(&arg) {
+                        Some(name) => env_sets.push(name),
+                        None => return None,
+                    }
+                }
+            }
+        }
+        if env_sets.is_empty() {
+            return None;
+        }
+        Some(DeleteArgs {
+            env_sets: env_sets,
+            yes: yes,
+            trash: trash,
+        })
+    }
+
+    /// `--format`が無い場合はdotenv形式で出力する。
+    fn parse_as_dump<I>(args: &mut I) -> Option<DumpArgs>
+        where I: Iterator<Item = String>
+    {
+        let mut env_set = None;
+        let mut format = DumpFormat::Dotenv;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-f" | "--format" => {
+                    match args.next().and_then(|name| DumpFormat::from_name(&name)) {
+                        Some(f) => format = f,
+                        None => return None,
+                    }
+                }
+                _ if env_set.is_none() => env_set = EnvSetName::new(&arg),
+                _ => return None,
+            }
+        }
+        env_set.map(|env_set| {
+            DumpArgs {
+                env_set: env_set,
+                format: format,
+            }
+        })
+    }
+
     fn parse_as_edit<I>(args: &mut I) -> Option<EnvSetName>
         where I: Iterator<Item = String>
     {
         args.next().and_then(|name| EnvSetName::new(&name))
     }
 
-    fn parse_as_new<I>(args: &mut I) -> Option<EnvSetName>
+    fn parse_as_encrypt<I>(args: &mut I) -> Option<EncryptArgs>
         where I: Iterator<Item = String>
     {
-        args.next().and_then(|name| EnvSetName::new(&name))
+        args.next().and_then(|name| EnvSetName::new(&name)).map(|env_set| {
+            EncryptArgs {
+                env_set: env_set,
+                key: args.next(),
+            }
+        })
     }
 
-    fn parse_as_run<I>(args: &mut I) -> Option<(EnvSetName, Command)>
+    fn parse_as_lint<I>(args: &mut I) -> Option<LintArgs>
         where I: Iterator<Item = String>
     {
-        args.next()
-            .and_then(|name| EnvSetName::new(&name))
-            .and_then(|env_set_name| {
-                args.next().map(|program| {
-                    let mut cmd = Command::new(&program);
-                    for i in args {
-                        cmd.arg(&i);
+        let mut env_sets = Vec::new();
+        let mut json = false;
+        for arg in args {
+            match arg.as_str() {
+                "--json" => json = true,
+                _ => {
+                    match EnvSetName::new(&arg) {
+                        Some(name) => env_sets.push(name),
+                        None => return None,
                     }
-                    (env_set_name, cmd)
+                }
+            }
+        }
+        Some(LintArgs {
+            env_sets: env_sets,
+            json: json,
+        })
+    }
+
+    /// `--format`が無い場合はYAML形式で作成する。
+    fn parse_as_new<I>(args: &mut I) -> Option<NewArgs>
+        where I: Iterator<Item = String>
+    {
+        let mut env_set = None;
+        let mut storage: &'static Storage = &storage::YamlStorage;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-f" | "--format" => {
+                    match args.next().and_then(|ext| storage::from_extension(&ext)) {
+                        Some(s) => storage = s,
+                        None => return None,
+                    }
+                }
+                _ if env_set.is_none() => env_set = EnvSetName::new(&arg),
+                _ => return None,
+            }
+        }
+        env_set.map(|env_set| {
+            NewArgs {
+                env_set: env_set,
+                storage: storage,
+            }
+        })
+    }
+
+    /// `run [-v] [--clean [--keep VAR]...] [-e ENV_SET]... [ENV_SET[,ENV_SET]...] [--] COMMAND`
+    /// の形式を受け付ける。
+    /// `-e`が一つも無い場合は、最初の位置引数をカンマ区切りの`EnvSet`名のリストとして扱う。
+    /// オプションは`EnvSet`名より前に書く。`-`で始まるコマンドは`--`の後に書く。
+    fn parse_as_run<I>(args: &mut I) -> Option<RunArgs>
+        where I: Iterator<Item = String>
+    {
+        let mut env_sets = Vec::new();
+        let mut verbose = false;
+        let mut clean = false;
+        let mut keep = Vec::new();
+        let mut program = None;
+        let mut separated = false;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-v" | "--verbose" => verbose = true,
+                "--clean" | "--isolate" => clean = true,
+                "--keep" => {
+                    match args.next() {
+                        Some(names) => keep.extend(names.split(',').map(|name| name.to_owned())),
+                        None => return None,
+                    }
+                }
+                "-e" | "--env-set" => {
+                    match args.next().and_then(|names| Self::parse_env_set_names(&names)) {
+                        Some(names) => env_sets.extend(names),
+                        None => return None,
+                    }
+                }
+                "--" => {
+                    separated = true;
+                    break;
+                }
+                _ if arg.starts_with('-') => return None,
+                _ => {
+                    program = Some(arg);
+                    break;
+                }
+            }
+        }
+
+        if env_sets.is_empty() {
+            env_sets = match program.take().and_then(|names| Self::parse_env_set_names(&names)) {
+                Some(names) => names,
+                None => return None,
+            };
+        }
+        if program.is_none() {
+            program = match args.next() {
+                Some(ref arg) if !separated && arg == "--" => args.next(),
+                // `run foo --clean cmd`のように`EnvSet`名の後に書かれたオプションを、コマンドとして扱わない。
+                Some(ref arg) if !separated && arg.starts_with('-') => return None,
+                arg => arg,
+            };
+        }
+
+        program.map(|program| {
+            let mut cmd = Command::new(&program);
+            for i in args {
+                cmd.arg(&i);
+            }
+            RunArgs {
+                env_sets: env_sets,
+                verbose: verbose,
+                clean: clean,
+                keep: keep,
+                cmd: cmd,
+            }
+        })
+    }
+
+    /// `--shell`が無い場合は`bash`向けに出力する。
+    fn parse_as_export<I>(args: &mut I) -> Option<ExportArgs>
+        where I: Iterator<Item = String>
+    {
+        let mut env_set = None;
+        let mut shell = Shell::Bash;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-s" | "--shell" => {
+                    match args.next().and_then(|name| Shell::from_name(&name)) {
+                        Some(s) => shell = s,
+                        None => return None,
+                    }
+                }
+                _ if env_set.is_none() => env_set = EnvSetName::new(&arg),
+                _ => return None,
+            }
+        }
+        env_set.map(|env_set| {
+            ExportArgs {
+                env_set: env_set,
+                shell: shell,
+            }
+        })
+    }
+
+    fn parse_as_get<I>(args: &mut I) -> Option<GetArgs>
+        where I: Iterator<Item = String>
+    {
+        match (args.next().and_then(|name| EnvSetName::new(&name)), args.next(), args.next()) {
+            (Some(env_set), Some(key), None) => {
+                Some(GetArgs {
+                    env_set: env_set,
+                    key: key,
                 })
-            })
+            }
+            _ => None,
+        }
+    }
+
+    /// 値は最初の`=`より後の全てとする。
+    fn parse_as_set<I>(args: &mut I) -> Option<SetArgs>
+        where I: Iterator<Item = String>
+    {
+        let env_set: EnvSetName = match args.next().and_then(|name| EnvSetName::new(&name)) {
+            Some(env_set) => env_set,
+            None => return None,
+        };
+        let mut vars = Vec::new();
+        for arg in args {
+            match arg.find('=') {
+                Some(pos) => vars.push((arg[..pos].to_owned(), arg[pos + 1..].to_owned())),
+                None => return None,
+            }
+        }
+        if vars.is_empty() {
+            return None;
+        }
+        Some(SetArgs {
+            env_set: env_set,
+            vars: vars,
+        })
+    }
+
+    fn parse_as_unset<I>(args: &mut I) -> Option<UnsetArgs>
+        where I: Iterator<Item = String>
+    {
+        let env_set: EnvSetName = match args.next().and_then(|name| EnvSetName::new(&name)) {
+            Some(env_set) => env_set,
+            None => return None,
+        };
+        let keys: Vec<String> = args.collect();
+        if keys.is_empty() {
+            return None;
+        }
+        Some(UnsetArgs {
+            env_set: env_set,
+            keys: keys,
+        })
+    }
+
+    /// `--from`が無い場合はカレントディレクトリの`.env`を読み込む。
+    fn parse_as_import<I>(args: &mut I) -> Option<ImportArgs>
+        where I: Iterator<Item = String>
+    {
+        let mut env_set = None;
+        let mut from = PathBuf::from(".env");
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-f" | "--from" => {
+                    match args.next() {
+                        Some(path) => from = PathBuf::from(path),
+                        None => return None,
+                    }
+                }
+                _ if env_set.is_none() => env_set = EnvSetName::new(&arg),
+                _ => return None,
+            }
+        }
+        env_set.map(|env_set| {
+            ImportArgs {
+                env_set: env_set,
+                from: from,
+            }
+        })
+    }
+
+    fn parse_as_show<I>(args: &mut I) -> Option<ShowArgs>
+        where I: Iterator<Item = String>
+    {
+        let mut env_set = None;
+        let mut mask = false;
+        for arg in args {
+            match arg.as_str() {
+                "-m" | "--mask" => mask = true,
+                _ if env_set.is_none() => env_set = EnvSetName::new(&arg),
+                _ => return None,
+            }
+        }
+        env_set.map(|env_set| {
+            ShowArgs {
+                env_set: env_set,
+                mask: mask,
+            }
+        })
+    }
+
+    fn parse_env_set_names(names: &str) -> Option<Vec<EnvSetName>> {
+        names.split(',').map(EnvSetName::new).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CmdArgs, RunArgs};
+
+    fn parse_as_run(args: &[&str]) -> Option<RunArgs> {
+        CmdArgs::parse_as_run(&mut args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn env_set_names(args: &RunArgs) -> Vec<&str> {
+        args.env_sets.iter().map(|name| &**name).collect()
+    }
+
+    fn command_line(args: &RunArgs) -> Vec<String> {
+        Some(args.cmd.get_program())
+            .into_iter()
+            .chain(args.cmd.get_args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_as_run() {
+        let args = parse_as_run(&["-v", "--clean", "--keep", "A,B", "base,app", "cmd", "-e", "x"])
+                       .unwrap();
+        assert!(args.verbose && args.clean);
+        assert_eq!(args.keep, vec!["A", "B"]);
+        assert_eq!(env_set_names(&args), vec!["base", "app"]);
+        assert_eq!(command_line(&args), vec!["cmd", "-e", "x"]);
+
+        let args = parse_as_run(&["-e", "base,db", "--env-set", "app", "--", "-cmd", "--clean"])
+                       .unwrap();
+        assert!(!args.clean);
+        assert_eq!(env_set_names(&args), vec!["base", "db", "app"]);
+        assert_eq!(command_line(&args), vec!["-cmd", "--clean"]);
+
+        let args = parse_as_run(&["app", "--", "-cmd"]).unwrap();
+        assert_eq!(env_set_names(&args), vec!["app"]);
+        assert_eq!(command_line(&args), vec!["-cmd"]);
+
+        let args = parse_as_run(&["-e", "app", "cmd"]).unwrap();
+        assert_eq!(command_line(&args), vec!["cmd"]);
+    }
+
+    #[test]
+    fn test_parse_as_run_invalid() {
+        assert!(parse_as_run(&["app", "--clean", "cmd"]).is_none());
+        assert!(parse_as_run(&["--unknown", "app", "cmd"]).is_none());
+        assert!(parse_as_run(&["app"]).is_none());
+        assert!(parse_as_run(&["-e"]).is_none());
+        assert!(parse_as_run(&["base,../app", "cmd"]).is_none());
     }
 }
@@ -22,15 +22,155 @@
 
 use error::{Error, Result};
 use s_app_dir::{AppDir, XdgDir};
+use std::env;
+use std::ffi::OsString;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub fn config_dir() -> Result<PathBuf> {
     AppDir::new("envars").xdg_dir(XdgDir::Config).ok_or(Error::ConfigDirIsNotFound)
 }
 
-pub fn create_config_dir() -> Result<()> {
-    let config_dir: PathBuf = try!(config_dir());
-    try!(fs::create_dir_all(&config_dir));
+/// プロジェクトの`EnvSet`ファイルを置くディレクトリ名。
+pub const PROJECT_DIR_NAME: &'static str = ".envars";
+
+/// カレントディレクトリから親ディレクトリへ順に`.envars`ディレクトリを探す。
+pub fn project_dir() -> Option<PathBuf> {
+    env::current_dir().ok().and_then(|dir| find_project_dir(&dir))
+}
+
+/// `start`から親ディレクトリへ順に`.envars`ディレクトリを探す。
+fn find_project_dir(start: &Path) -> Option<PathBuf> {
+    let mut dir: PathBuf = start.to_path_buf();
+    loop {
+        let candidate: PathBuf = dir.join(PROJECT_DIR_NAME);
+        if candidate.is_dir() {
+            return Some(candidate);
+        }
+        if !dir.pop() {
+            return None;
+        }
+    }
+}
+
+/// 全てのユーザーで共有する`EnvSet`ファイルを置くディレクトリ。
+#[cfg(unix)]
+pub fn system_dir() -> Option<PathBuf> {
+    Some(PathBuf::from("/etc/envars"))
+}
+
+/// 全てのユーザーで共有する`EnvSet`ファイルを置くディレクトリ。
+#[cfg(not(unix))]
+pub fn system_dir() -> Option<PathBuf> {
+    env::var_os("PROGRAMDATA").map(|dir| PathBuf::from(dir).join("envars"))
+}
+
+/// `EnvSet`ファイルを探すディレクトリを優先される順に返す。
+///
+/// `--config-dir`で指定された`config_dirs`、環境変数`ENVARS_PATH`に列挙されたディレクトリ、
+/// 既定のディレクトリの順に最初に見つかったものを用いる。既定ではプロジェクトのディレクトリ、
+/// 設定ディレクトリ、`system_dir`の順に優先される。
+pub fn env_set_dirs(config_dirs: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    env_set_dirs_with(config_dirs, env::var_os("ENVARS_PATH"), || {
+        let mut dirs: Vec<PathBuf> = project_dir().into_iter().collect();
+        dirs.push(try!(config_dir()));
+        dirs.extend(system_dir());
+        Ok(dirs)
+    })
+}
+
+/// `env_set_dirs`と同様に返す。`ENVARS_PATH`の値と、既定のディレクトリを返す関数を与える。
+fn env_set_dirs_with<F>(config_dirs: &[PathBuf],
+                        envars_path: Option<OsString>,
+                        default_dirs: F)
+                        -> Result<Vec<PathBuf>>
+    where F: FnOnce() -> Result<Vec<PathBuf>>
+{
+    if !config_dirs.is_empty() {
+        return Ok(config_dirs.to_vec());
+    }
+    if let Some(paths) = envars_path {
+        let dirs: Vec<PathBuf> = env::split_paths(&paths)
+                                      .filter(|dir| !dir.as_os_str().is_empty())
+                                      .collect();
+        if !dirs.is_empty() {
+            return Ok(dirs);
+        }
+    }
+    default_dirs()
+}
+
+/// 新しい`EnvSet`ファイルを作るディレクトリ。最も優先されるディレクトリとなる。
+pub fn default_env_set_dir(dirs: &[PathBuf]) -> Result<PathBuf> {
+    dirs.first().cloned().ok_or(Error::ConfigDirIsNotFound)
+}
+
+/// 値の暗号化に用いる鍵ファイルのパス。
+/// `EnvSet`ファイルと共に共有されないよう、既定では`$XDG_DATA_HOME/envars/key`とする。
+/// 環境変数`ENVARS_KEY_FILE`で変更できる。
+pub fn key_file_path() -> Result<PathBuf> {
+    if let Some(path) = env::var_os("ENVARS_KEY_FILE") {
+        return Ok(PathBuf::from(path));
+    }
+    AppDir::new("envars")
+        .xdg_dir(XdgDir::Data)
+        .map(|dir| dir.join("key"))
+        .ok_or(Error::DataDirIsNotFound)
+}
+
+/// 新しい`EnvSet`ファイルを作るディレクトリが無ければ作る。
+pub fn create_default_env_set_dir(dirs: &[PathBuf]) -> Result<()> {
+    let dir: PathBuf = try!(default_env_set_dir(dirs));
+    try!(fs::create_dir_all(&dir));
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{PROJECT_DIR_NAME, env_set_dirs_with, find_project_dir};
+    use error::Error;
+    use std::env;
+    use std::ffi::OsString;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    #[test]
+    fn test_env_set_dirs_precedence() {
+        let defaults = || Ok(vec![PathBuf::from("project"), PathBuf::from("config")]);
+        let config_dirs = [PathBuf::from("a"), PathBuf::from("b")];
+        let envars_path: OsString = env::join_paths(&["c", "d"]).unwrap();
+
+        assert_eq!(env_set_dirs_with(&config_dirs, Some(envars_path.clone()), defaults).unwrap(),
+                   config_dirs);
+        assert_eq!(env_set_dirs_with(&[], Some(envars_path), defaults).unwrap(),
+                   vec![PathBuf::from("c"), PathBuf::from("d")]);
+        assert_eq!(env_set_dirs_with(&[], Some(OsString::new()), defaults).unwrap(),
+                   vec![PathBuf::from("project"), PathBuf::from("config")]);
+        assert_eq!(env_set_dirs_with(&[], None, defaults).unwrap(),
+                   vec![PathBuf::from("project"), PathBuf::from("config")]);
+        let not_found = || Err(Error::ConfigDirIsNotFound);
+        assert_eq!(env_set_dirs_with(&config_dirs, None, not_found).unwrap(), config_dirs);
+    }
+
+    #[test]
+    fn test_find_project_dir() {
+        let root: PathBuf = env::temp_dir()
+                                .join(format!("envars-find-project-dir-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let nested: PathBuf = root.join("project").join("src").join("bin");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir_all(root.join("project").join(PROJECT_DIR_NAME)).unwrap();
+        fs::create_dir_all(root.join("project").join("src").join(PROJECT_DIR_NAME)).unwrap();
+        fs::File::create(root.join(PROJECT_DIR_NAME)).unwrap();
+
+        let from_nested = find_project_dir(&nested);
+        let from_project = find_project_dir(&root.join("project"));
+        let from_root = find_project_dir(&root);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(from_nested, Some(root.join("project").join("src").join(PROJECT_DIR_NAME)));
+        assert_eq!(from_project, Some(root.join("project").join(PROJECT_DIR_NAME)));
+        assert!(from_root.map_or(true, |dir| !dir.starts_with(&root)));
+    }
+}
@@ -0,0 +1,231 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 Siphilia
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! dotenv形式(`.env`ファイル)の読み込み。
+//!
+//! 次の記述を受け付ける。
+//!
+//! * `#`で始まる行及び、引用符で囲まれていない値の後の` #`以降はコメント
+//! * 先頭の`export `は無視される
+//! * `'...'`の中は何も解釈されない
+//! * `"..."`の中では`\n`, `\r`, `\t`, `\"`, `\\`, `\$`がエスケープとして解釈される
+//! * 引用符で囲まれた値は複数行に渡ってもよい
+//!
+//! `EnvSet`では`${NAME}`が展開されるので、dotenvで展開されない`${`は`$${`として読み込む。
+
+use error::{Error, Result};
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::str::Chars;
+
+pub fn parse(content: &str) -> Result<BTreeMap<String, String>> {
+    let mut parser = Parser {
+        chars: content.chars().peekable(),
+        line: 1,
+    };
+    let mut set = BTreeMap::new();
+    while let Some((k, v)) = try!(parser.next_pair()) {
+        set.insert(k, v);
+    }
+    Ok(set)
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn next_pair(&mut self) -> Result<Option<(String, String)>> {
+        loop {
+            self.skip_blank();
+            match self.chars.peek().cloned() {
+                None => return Ok(None),
+                Some('\n') => self.bump(),
+                Some('#') => self.skip_line(),
+                Some(_) => break,
+            }
+        }
+
+        let mut key: String = self.read_key();
+        if key == "export" && self.chars.peek().map_or(false, |c| c.is_whitespace()) {
+            self.skip_blank();
+            key = self.read_key();
+        }
+        if key.is_empty() {
+            return Err(self.error("expected a variable name"));
+        }
+        self.skip_blank();
+        if self.chars.peek() != Some(&'=') {
+            return Err(self.error(&format!("expected '=' after {}", key)));
+        }
+        self.bump();
+        self.skip_blank();
+
+        let value = match self.chars.peek().cloned() {
+            Some('\'') => try!(self.read_single_quoted()),
+            Some('"') => try!(self.read_double_quoted()),
+            _ => return Ok(Some((key, self.read_unquoted()))),
+        };
+        self.skip_blank();
+        match self.chars.peek().cloned() {
+            None | Some('\n') => {}
+            Some('#') => self.skip_line(),
+            Some(_) => {
+                let message = format!("unexpected characters after the value of {}", key);
+                return Err(self.error(&message));
+            }
+        }
+        Ok(Some((key, value)))
+    }
+
+    fn read_key(&mut self) -> String {
+        let mut key = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if c == '=' || c.is_whitespace() {
+                break;
+            }
+            key.push(c);
+            self.bump();
+        }
+        key
+    }
+
+    /// 行末までを値とする。空白の後の`#`以降はコメントとして取り除く。
+    fn read_unquoted(&mut self) -> String {
+        let mut value = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if c == '\n' || (c == '#' && value.ends_with(char::is_whitespace)) {
+                break;
+            }
+            value.push(c);
+            self.bump();
+        }
+        self.skip_line();
+        value.trim().to_owned()
+    }
+
+    fn read_single_quoted(&mut self) -> Result<String> {
+        self.bump();
+        let mut value = String::new();
+        loop {
+            match self.chars.next() {
+                Some('\'') => return Ok(value.replace("${", "$${")),
+                Some(c) => self.push(&mut value, c),
+                None => return Err(self.error("unterminated single quote")),
+            }
+        }
+    }
+
+    fn read_double_quoted(&mut self) -> Result<String> {
+        self.bump();
+        let mut value = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(value),
+                Some('\\') => {
+                    match self.chars.next() {
+                        Some('n') => value.push('\n'),
+                        Some('r') => value.push('\r'),
+                        Some('t') => value.push('\t'),
+                        Some('$') if self.chars.peek() == Some(&'{') => value.push_str("$$"),
+                        Some(c @ '"') | Some(c @ '\\') | Some(c @ '$') => value.push(c),
+                        Some(c) => {
+                            value.push('\\');
+                            self.push(&mut value, c);
+                        }
+                        None => return Err(self.error("unterminated double quote")),
+                    }
+                }
+                Some(c) => self.push(&mut value, c),
+                None => return Err(self.error("unterminated double quote")),
+            }
+        }
+    }
+
+    fn push(&mut self, value: &mut String, c: char) {
+        if c == '\n' {
+            self.line += 1;
+        }
+        value.push(c);
+    }
+
+    fn bump(&mut self) {
+        if self.chars.next() == Some('\n') {
+            self.line += 1;
+        }
+    }
+
+    /// 改行以外の空白を読み飛ばす。
+    fn skip_blank(&mut self) {
+        while self.chars.peek().map_or(false, |&c| c != '\n' && c.is_whitespace()) {
+            self.bump();
+        }
+    }
+
+    /// 次の行の先頭まで読み飛ばす。
+    fn skip_line(&mut self) {
+        while let Some(c) = self.chars.next() {
+            if c == '\n' {
+                self.line += 1;
+                break;
+            }
+        }
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::ParseDotenv(self.line, message.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use error::Error;
+
+    #[test]
+    fn test_parse() {
+        let set = parse("# comment\n\
+                         export A=plain value # comment\n\
+                         B = 'single ${X} \"#'\n\
+                         C=\"double\\n\\\"${X}\\\" \\${Y}\n\
+                         next line\" # comment\n\
+                         D=\n")
+                      .unwrap();
+        assert_eq!(set["A"], "plain value");
+        assert_eq!(set["B"], "single $${X} \"#");
+        assert_eq!(set["C"], "double\n\"${X}\" $${Y}\nnext line");
+        assert_eq!(set["D"], "");
+    }
+
+    #[test]
+    fn test_parse_error() {
+        match parse("A=1\nB='unterminated\n\n") {
+            Err(Error::ParseDotenv(line, _)) => assert_eq!(line, 4),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match parse("A=1\nB\n") {
+            Err(Error::ParseDotenv(line, _)) => assert_eq!(line, 2),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
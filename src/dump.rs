@@ -0,0 +1,186 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 Siphilia
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use envset;
+use envset::EnvSet;
+use error::{Error, Result};
+use serde_json::Map;
+use serde_json::Value as Json;
+
+/// `dump`モードの出力形式。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DumpFormat {
+    Dotenv,
+    Json,
+    /// systemdの`EnvironmentFile=`で読み込める形式。
+    Systemd,
+    /// `docker run --env-file`で読み込める形式。
+    DockerEnv,
+}
+
+impl DumpFormat {
+    pub fn from_name(name: &str) -> Option<DumpFormat> {
+        match name {
+            "dotenv" => Some(DumpFormat::Dotenv),
+            "json" => Some(DumpFormat::Json),
+            "systemd" => Some(DumpFormat::Systemd),
+            "docker-env" => Some(DumpFormat::DockerEnv),
+            _ => None,
+        }
+    }
+
+    /// `EnvSet`の変数をこの形式の文字列にする。
+    /// 取り除く変数はJSONでは`null`となり、それ以外の形式では表現できないので出力されない。
+    pub fn dump(&self, env_set: &EnvSet) -> Result<String> {
+        let format_value: fn(&str, &str) -> Result<String> = match *self {
+            DumpFormat::Dotenv => dotenv_value,
+            DumpFormat::Json => return Ok(dump_json(env_set)),
+            DumpFormat::Systemd => systemd_value,
+            DumpFormat::DockerEnv => docker_env_value,
+        };
+
+        let mut buf = String::new();
+        for (k, v) in env_set.iter() {
+            let value: String = try!(format_value(k, v));
+            buf.push_str(&format!("{}={}\n", k, value));
+        }
+        Ok(buf)
+    }
+}
+
+fn dump_json(env_set: &EnvSet) -> String {
+    let mut object: Map<String, Json> = env_set.iter()
+                                               .map(|(k, v)| (k.clone(), Json::String(v.clone())))
+                                               .collect();
+    object.extend(env_set.unset_vars().map(|k| (k.clone(), Json::Null)));
+    format!("{:#}\n", Json::Object(object))
+}
+
+/// `'`と改行を含まない値はシングルクォートで、それ以外はダブルクォートで囲む。
+fn dotenv_value(key: &str, value: &str) -> Result<String> {
+    try!(envset::check_portable_name(key));
+    if !value.contains('\'') && !value.contains('\n') {
+        return Ok(format!("'{}'", value));
+    }
+    let mut buf = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\\' => buf.push_str(r"\\"),
+            '"' => buf.push_str("\\\""),
+            '$' => buf.push_str(r"\$"),
+            '\n' => buf.push_str(r"\n"),
+            '\r' => buf.push_str(r"\r"),
+            '\t' => buf.push_str(r"\t"),
+            _ => buf.push(c),
+        }
+    }
+    buf.push('"');
+    Ok(buf)
+}
+
+/// systemdはダブルクォートの中で`\`, `"`, `` ` ``, `$`のエスケープを解釈し、改行はそのまま保持する。
+fn systemd_value(key: &str, value: &str) -> Result<String> {
+    try!(envset::check_portable_name(key));
+    let mut buf = String::from("\"");
+    for c in value.chars() {
+        if c == '\\' || c == '"' || c == '`' || c == '$' {
+            buf.push('\\');
+        }
+        buf.push(c);
+    }
+    buf.push('"');
+    Ok(buf)
+}
+
+/// dockerは行末までをそのまま値として扱うので、改行を含む値は表現できない。
+fn docker_env_value(key: &str, value: &str) -> Result<String> {
+    if value.contains('\n') {
+        Err(Error::UnsupportedValue(key.to_owned(), "docker-env can't contain newlines".to_owned()))
+    } else {
+        Ok(value.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DumpFormat;
+    use dotenv;
+    use envset::{EnvSet, EnvSetName};
+    use error::Error;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    fn env_set(vars: &[(&str, &str)]) -> EnvSet {
+        let name = EnvSetName::new("test").unwrap();
+        let mut env_set = EnvSet::empty(&[PathBuf::from("envars")], &name).unwrap();
+        for &(k, v) in vars {
+            env_set.set_env(k.to_owned(), v.to_owned());
+        }
+        env_set
+    }
+
+    #[test]
+    fn test_dump_dotenv() {
+        let vars = [("A", "plain $HOME"), ("B", "it's \"$HOME\"\n\t\\")];
+        let dumped = DumpFormat::Dotenv.dump(&env_set(&vars)).unwrap();
+        assert_eq!(dumped, "A='plain $HOME'\nB=\"it's \\\"\\$HOME\\\"\\n\\t\\\\\"\n");
+        let parsed = dotenv::parse(&dumped).unwrap();
+        let expected: BTreeMap<String, String> = vars.iter()
+                                                      .map(|&(k, v)| (k.to_owned(), v.to_owned()))
+                                                      .collect();
+        assert_eq!(parsed, expected);
+    }
+
+    #[test]
+    fn test_dump_systemd() {
+        let dumped = DumpFormat::Systemd.dump(&env_set(&[("A", "`$x` \"\\\"\nnext")])).unwrap();
+        assert_eq!(dumped, "A=\"\\`\\$x\\` \\\"\\\\\\\"\nnext\"\n");
+    }
+
+    #[test]
+    fn test_dump_docker_env() {
+        assert_eq!(DumpFormat::DockerEnv.dump(&env_set(&[("A", "'$x' \"y\"")])).unwrap(),
+                   "A='$x' \"y\"\n");
+        match DumpFormat::DockerEnv.dump(&env_set(&[("A", "a\nb")])) {
+            Err(Error::UnsupportedValue(ref key, _)) => assert_eq!(key, "A"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_dump_json() {
+        let mut env_set = env_set(&[("A", "\"quoted\"\n")]);
+        env_set.unset_env("B".to_owned());
+        assert_eq!(DumpFormat::Json.dump(&env_set).unwrap(),
+                   "{\n  \"A\": \"\\\"quoted\\\"\\n\",\n  \"B\": null\n}\n");
+    }
+
+    #[test]
+    fn test_dump_hostile_key() {
+        for format in &[DumpFormat::Dotenv, DumpFormat::Systemd] {
+            match format.dump(&env_set(&[("A;id", "v")])) {
+                Err(Error::UnsupportedName(ref key, _)) => assert_eq!(key, "A;id"),
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+}
@@ -21,19 +21,24 @@
 // SOFTWARE.
 
 use config;
-use error::{Error, Result};
-use std::collections::BTreeMap;
-use std::collections::btree_map;
+use error::{Error, Result, SyntaxError};
+use interpolation;
+use secret;
+use secret::Key;
+use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{btree_map, btree_set};
+use std::env;
+use std::fmt;
 use std::fs;
 use std::io;
 use std::io::{Read, Write};
 use std::ops;
 use std::path;
-use yaml_rust as yaml;
-use yaml_rust::Yaml;
+use storage;
+use storage::{LoadError, Storage, Table, Value};
 
 /// `EnvSet`の名前。`ENV_SET_NAME.yaml`としてファイル名に用いられる。
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub struct EnvSetName(String);
 
 impl EnvSetName {
@@ -57,6 +62,12 @@ impl ops::Deref for EnvSetName {
     }
 }
 
+impl fmt::Display for EnvSetName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
 impl AsRef<path::Path> for EnvSetName {
     fn as_ref(&self) -> &path::Path {
         &self.0.as_ref()
@@ -82,12 +93,32 @@ impl AsRef<[u8]> for EnvSetName {
 }
 
 
+/// 継承元の`EnvSet`を列挙するためのキー。環境変数としては扱われない。
+pub const EXTENDS_KEY: &'static str = "extends";
+
+/// ファイル全体が暗号化された`EnvSet`ファイルの拡張子。`foo.yaml.enc`のように元の拡張子の後に付ける。
+pub const ENCRYPTED_EXTENSION: &'static str = "enc";
+
 /// `EnvSet`のメモリ上での表現型。
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct EnvSet {
     name: EnvSetName,
     path: path::PathBuf,
+    extends: Vec<EnvSetName>,
+    set: BTreeMap<String, String>,
+    /// 子プロセスの環境から取り除く変数名。YAMLでは値を`null`(`~`)にして表す。
+    unset: BTreeSet<String>,
+    /// 復号した値、またはそれを参照して展開した値を持つ変数名。
+    secrets: BTreeSet<String>,
+    /// この`EnvSet`と、継承や重ね合わせで取り込んだ`EnvSet`の名前。継承元が先に並ぶ。
+    lineage: Vec<EnvSetName>,
+}
+
+/// `EnvSet`ファイルから読み取った、継承を解決する前の内容。
+struct EnvSetContent {
+    extends: Vec<EnvSetName>,
     set: BTreeMap<String, String>,
+    unset: BTreeSet<String>,
 }
 
 /// CRUD操作を次の様に定義する
@@ -98,28 +129,60 @@ pub struct EnvSet {
 ///
 /// オブジェクトのファイルへの保存: `write_to_file`関数を用意し、その実行でファイルに書き出す。
 impl EnvSet {
-    pub fn new(env_set_name: &EnvSetName) -> Result<EnvSet> {
-        let yaml_path = try!(Self::yaml_file_path(&env_set_name));
-        Self::read_env_set_yaml(&yaml_path).map(|env_set_yaml| {
+    /// `extends`で指定された継承元を再帰的に解決した`EnvSet`を返す。
+    /// 継承元は記述順に適用され、後のものほど優先される。子の値は常に継承元の値より優先される。
+    pub fn new(dirs: &[path::PathBuf], env_set_name: &EnvSetName) -> Result<EnvSet> {
+        Self::resolve(dirs, env_set_name, &mut Vec::new())
+    }
+
+    /// 継承元を解決せず、`EnvSet`ファイルに書かれた内容だけを読み込む。
+    pub fn load(dirs: &[path::PathBuf], env_set_name: &EnvSetName) -> Result<EnvSet> {
+        let path = try!(Self::find_file_path(dirs, &env_set_name));
+        Self::read_env_set_file(env_set_name, &path).map(|content| {
             EnvSet {
                 name: env_set_name.clone(),
-                path: yaml_path,
-                set: env_set_yaml,
+                path: path,
+                extends: content.extends,
+                set: content.set,
+                unset: content.unset,
+                secrets: BTreeSet::new(),
+                lineage: vec![env_set_name.clone()],
             }
         })
     }
 
-    pub fn empty(env_set_name: &EnvSetName) -> Result<EnvSet> {
-        let path: path::PathBuf = try!(Self::yaml_file_path(&env_set_name));
+    /// YAML形式で保存される空の`EnvSet`を返す。
+    pub fn empty(dirs: &[path::PathBuf], env_set_name: &EnvSetName) -> Result<EnvSet> {
+        Self::empty_in(dirs, env_set_name, &storage::YamlStorage)
+    }
+
+    /// `storage`の形式で保存される空の`EnvSet`を返す。
+    pub fn empty_in(dirs: &[path::PathBuf],
+                    env_set_name: &EnvSetName,
+                    storage: &Storage)
+                    -> Result<EnvSet> {
+        let path: path::PathBuf = try!(Self::file_path_in(dirs, &env_set_name, storage));
         Ok(EnvSet {
             name: env_set_name.clone(),
             path: path,
+            extends: Vec::new(),
             set: BTreeMap::new(),
+            unset: BTreeSet::new(),
+            secrets: BTreeSet::new(),
+            lineage: vec![env_set_name.clone()],
         })
     }
 
+    pub fn name(&self) -> &EnvSetName {
+        &self.name
+    }
+
+    pub fn extends(&self) -> &[EnvSetName] {
+        &self.extends
+    }
+
     pub fn is_empty(&self) -> bool {
-        self.set.is_empty()
+        self.set.is_empty() && self.unset.is_empty()
     }
 
     pub fn iter(&self) -> EnvSetIterator {
@@ -131,9 +194,102 @@ impl EnvSet {
     }
 
     pub fn set_env(&mut self, key: String, value: String) {
+        self.unset.remove(&key);
+        self.secrets.remove(&key);
         self.set.insert(key, value);
     }
 
+    /// 変数を子プロセスの環境から取り除くよう指定する。
+    pub fn unset_env(&mut self, key: String) {
+        self.set.remove(&key);
+        self.secrets.remove(&key);
+        self.unset.insert(key);
+    }
+
+    /// 変数の指定を`EnvSet`から削除する。削除した場合に`true`を返す。
+    pub fn remove_env(&mut self, key: &str) -> bool {
+        let removed = self.set.remove(key).is_some();
+        self.secrets.remove(key);
+        self.unset.remove(key) || removed
+    }
+
+    /// 子プロセスの環境から取り除く変数名の`Iterator`を返す。
+    pub fn unset_vars(&self) -> btree_set::Iter<String> {
+        self.unset.iter()
+    }
+
+    pub fn is_unset(&self, key: &str) -> bool {
+        self.unset.contains(key)
+    }
+
+    /// `other`の変数をこの`EnvSet`に重ね合わせる。同じ変数名の値は`other`が優先される。
+    /// `other`で取り除くよう指定された変数は、この`EnvSet`の値を打ち消す。
+    pub fn merge(&mut self, other: EnvSet) {
+        for k in other.set.keys() {
+            self.unset.remove(k);
+            self.secrets.remove(k);
+        }
+        for k in &other.unset {
+            self.set.remove(k);
+            self.secrets.remove(k);
+        }
+        self.set.extend(other.set);
+        self.unset.extend(other.unset);
+        self.secrets.extend(other.secrets);
+        for name in other.lineage {
+            if !self.lineage.contains(&name) {
+                self.lineage.push(name);
+            }
+        }
+    }
+
+    /// この`EnvSet`と、`extends`で直接または間接に継承している全ての`EnvSet`の名前を返す。
+    /// 重ね合わせた場合は、重ねた`EnvSet`とその継承元も含む。継承元が先に並び、同じ`EnvSet`は一度だけ現れる。
+    pub fn lineage(&self) -> &[EnvSetName] {
+        &self.lineage
+    }
+
+    /// 暗号化された値を含む場合に`true`を返す。
+    pub fn has_encrypted(&self) -> bool {
+        self.set.values().any(|v| secret::is_encrypted(v))
+    }
+
+    /// 暗号化された値を全て復号する。復号した値は`interpolate`で展開されない。
+    pub fn decrypt(&mut self, key: &Key) -> Result<()> {
+        for (k, v) in self.set.iter_mut().filter(|&(_, ref v)| secret::is_encrypted(v)) {
+            *v = try!(key.decrypt(v, k).ok_or_else(|| Error::DecryptFailed(k.clone())));
+            self.secrets.insert(k.clone());
+        }
+        Ok(())
+    }
+
+    /// 変数が復号した値、またはそれを参照して展開した値を持つ場合に`true`を返す。
+    pub fn is_secret(&self, key: &str) -> bool {
+        self.secrets.contains(key)
+    }
+
+    /// 値の中の`${NAME}`参照を、同じ`EnvSet`の変数、次いで親プロセスの環境変数から展開する。
+    pub fn interpolate(&mut self) -> Result<()> {
+        self.interpolate_with(|name| env::var(name).ok())
+    }
+
+    /// `interpolate`と同様に展開する。親プロセスの環境変数の代わりに`env`を用いる。
+    pub fn interpolate_with<F>(&mut self, env: F) -> Result<()>
+        where F: Fn(&str) -> Option<String>
+    {
+        let unset = &self.unset;
+        let (set, secrets) = try!(interpolation::expand(&self.set, &self.secrets, |name| {
+            if unset.contains(name) {
+                None
+            } else {
+                env(name)
+            }
+        }));
+        self.set = set;
+        self.secrets = secrets;
+        Ok(())
+    }
+
     pub fn file_path(&self) -> &path::Path {
         self.path.as_path()
     }
@@ -142,72 +298,367 @@ impl EnvSet {
         fs::remove_file(self.file_path())
     }
 
+    /// ファイルの拡張子に応じた形式で書き出す。
     pub fn write_to_file(&self) -> Result<()> {
-        let mut file: fs::File = try!(fs::File::create(self.file_path()));
-        let yaml: String = try!(self.make_yaml());
-        try!(file.write_all(yaml.as_bytes()));
+        let content: String = try!(Self::storage_of(self.file_path()).dump(&self.make_table()));
+        Self::write_file(self.file_path(), &content)
+    }
+
+    /// `EnvSet`ファイルの内容を読み込む。ファイル全体が暗号化されている場合は復号した内容を返す。
+    pub fn read_file<P: AsRef<path::Path>>(path: P) -> Result<String> {
+        let content: String = try!(Self::read_file_content(&path));
+        if !is_encrypted_file(&path) {
+            return Ok(content);
+        }
+        let key: Key = try!(Key::load());
+        key.decrypt(content.trim(), "")
+           .ok_or_else(|| Error::DecryptFailed(path.as_ref().display().to_string()))
+    }
+
+    /// `EnvSet`ファイルに内容を書き出す。暗号化する拡張子の場合はファイル全体を暗号化する。
+    pub fn write_file<P: AsRef<path::Path>>(path: P, content: &str) -> Result<()> {
+        let content: String = if is_encrypted_file(&path) {
+            format!("{}\n", try!(try!(Key::load_or_create()).encrypt(content, "")))
+        } else {
+            content.to_owned()
+        };
+        let mut file: fs::File = try!(fs::File::create(path));
+        try!(file.write_all(content.as_bytes()));
         try!(file.sync_data());
         Ok(())
     }
 
-    pub fn does_env_set_exists(env_set_name: &EnvSetName) -> Result<bool> {
-        let path: path::PathBuf = try!(EnvSet::yaml_file_path(&env_set_name));
-        match fs::metadata(&path) {
-            Ok(_) => Ok(true),
-            Err(error) => {
-                match error.kind() {
-                    io::ErrorKind::NotFound => Ok(false),
-                    _ => Err(From::from(error)),
-                }
+    /// 新しい`EnvSet`ファイルを作るディレクトリに、同じ名前の`EnvSet`ファイルがあるかを返す。
+    /// 優先されないディレクトリにある`EnvSet`は、新しい`EnvSet`で隠されるだけなので含めない。
+    pub fn does_env_set_exists(dirs: &[path::PathBuf], env_set_name: &EnvSetName) -> Result<bool> {
+        let dir: path::PathBuf = try!(config::default_env_set_dir(dirs));
+        for path in Self::candidate_paths_in(&dir, env_set_name) {
+            match fs::metadata(&path) {
+                Ok(_) => return Ok(true),
+                Err(ref error) if error.kind() == io::ErrorKind::NotFound => {}
+                Err(error) => return Err(From::from(error)),
             }
         }
+        Ok(false)
     }
 
-    pub fn yaml_file_path<P: AsRef<path::Path>>(env_set_name: P) -> Result<path::PathBuf> {
-        config::config_dir().map(|path| path.join(env_set_name).with_extension("yaml"))
+    /// `EnvSet`ファイルを探す全てのディレクトリにある`EnvSet`の名前を返す。
+    pub fn names(dirs: &[path::PathBuf]) -> Result<Vec<EnvSetName>> {
+        let mut names = BTreeSet::new();
+        for dir in dirs {
+            if dir.is_dir() {
+                names.extend(try!(Self::names_in(dir)));
+            }
+        }
+        Ok(names.into_iter().collect())
     }
 
-    fn make_yaml(&self) -> Result<String> {
-        let mut buf = String::new();
-        {
-            let mut emitter = yaml::YamlEmitter::new(&mut buf);
-            let yaml_hash = yaml::Yaml::Hash(self.set
-                                                 .iter()
-                                                 .map(|(k, v)| (yaml_str(k), yaml_str(v)))
-                                                 .collect());
-            try!(emitter.dump(&yaml_hash).map_err(Error::EmitYaml));
+    /// ディレクトリにある`EnvSet`の名前を返す。
+    pub fn names_in<P: AsRef<path::Path>>(dir: P) -> Result<Vec<EnvSetName>> {
+        let mut names = BTreeSet::new();
+        for entry in try!(fs::read_dir(dir)) {
+            let path: path::PathBuf = try!(entry).path();
+            let plain_path: path::PathBuf = decrypted_path(&path);
+            if storage::from_path(&plain_path).is_none() || !path.is_file() {
+                continue;
+            }
+            let name = plain_path.file_stem()
+                                 .and_then(|stem| stem.to_str())
+                                 .and_then(EnvSetName::new);
+            if let Some(name) = name {
+                names.insert(name);
+            }
+        }
+        Ok(names.into_iter().collect())
+    }
+
+    pub fn yaml_file_path<P: AsRef<path::Path>>(dirs: &[path::PathBuf],
+                                                env_set_name: P)
+                                                -> Result<path::PathBuf> {
+        Self::file_path_in(dirs, env_set_name, &storage::YamlStorage)
+    }
+
+    pub fn file_path_in<P: AsRef<path::Path>>(dirs: &[path::PathBuf],
+                                              env_set_name: P,
+                                              storage: &Storage)
+                                              -> Result<path::PathBuf> {
+        config::default_env_set_dir(dirs)
+            .map(|dir| Self::file_path_in_dir(dir, env_set_name, storage))
+    }
+
+    fn file_path_in_dir<D, P>(dir: D, env_set_name: P, storage: &Storage) -> path::PathBuf
+        where D: AsRef<path::Path>,
+              P: AsRef<path::Path>
+    {
+        dir.as_ref().join(env_set_name).with_extension(storage.extension())
+    }
+
+    /// 対応しているいずれかの形式で存在する`EnvSet`ファイルのパスを、優先されるディレクトリから順に探して返す。
+    /// どの形式のファイルも無い場合は新しい`EnvSet`ファイルを作るディレクトリのYAML形式のパスを返す。
+    pub fn find_file_path<P: AsRef<path::Path>>(dirs: &[path::PathBuf],
+                                                env_set_name: P)
+                                                -> Result<path::PathBuf> {
+        for dir in dirs {
+            if let Some(path) = Self::find_file_path_in(dir, &env_set_name) {
+                return Ok(path);
+            }
         }
-        Ok(buf.clone())
+        Self::yaml_file_path(dirs, env_set_name)
+    }
+
+    /// ディレクトリにある`EnvSet`ファイルのパスを返す。
+    pub fn find_file_path_in<D, P>(dir: D, env_set_name: P) -> Option<path::PathBuf>
+        where D: AsRef<path::Path>,
+              P: AsRef<path::Path>
+    {
+        Self::candidate_paths_in(dir, env_set_name).into_iter().find(|path| path.is_file())
     }
 
-    fn read_env_set_yaml<P: AsRef<path::Path>>(yaml_path: P) -> Result<BTreeMap<String, String>> {
-        let content: String = try!(Self::read_file_content(yaml_path));
-        let root: Vec<yaml::Yaml> = try!(yaml::YamlLoader::load_from_str(&content));
-        Ok(root.first()
-               .and_then(|yaml| {
-                   yaml.as_hash()
-                       .map(|o| Self::make_set(&o))
-               })
-               .unwrap_or_default())
+    /// ディレクトリ内の`EnvSet`ファイルのパスを探す順に返す。各形式について、暗号化されていないファイルを先に探す。
+    pub fn candidate_paths_in<D, P>(dir: D, env_set_name: P) -> Vec<path::PathBuf>
+        where D: AsRef<path::Path>,
+              P: AsRef<path::Path>
+    {
+        let mut paths = Vec::new();
+        for storage in storage::STORAGES {
+            let path: path::PathBuf = Self::file_path_in_dir(&dir, &env_set_name, *storage);
+            let encrypted: path::PathBuf = encrypted_path(&path);
+            paths.push(path);
+            paths.push(encrypted);
+        }
+        paths
     }
 
-    fn make_set(orig: &BTreeMap<Yaml, Yaml>) -> BTreeMap<String, String> {
+    /// 拡張子が対応していない形式の場合はYAML形式とみなす。
+    fn storage_of<P: AsRef<path::Path>>(path: P) -> &'static Storage {
+        storage::from_path(decrypted_path(path)).unwrap_or(&storage::YamlStorage)
+    }
+
+    /// `chain`には解決中の`EnvSet`名が継承の順に積まれており、循環の検出に用いる。
+    fn resolve(dirs: &[path::PathBuf],
+               env_set_name: &EnvSetName,
+               chain: &mut Vec<EnvSetName>)
+               -> Result<EnvSet> {
+        if let Some(pos) = chain.iter().position(|name| name == env_set_name) {
+            let mut cycle: Vec<EnvSetName> = chain[pos..].to_vec();
+            cycle.push(env_set_name.clone());
+            return Err(Error::InheritanceCycle(cycle));
+        }
+
+        let own: EnvSet = try!(Self::load(dirs, env_set_name));
+        let mut env_set = EnvSet {
+            name: own.name.clone(),
+            path: own.path.clone(),
+            extends: own.extends.clone(),
+            set: BTreeMap::new(),
+            unset: BTreeSet::new(),
+            secrets: BTreeSet::new(),
+            lineage: Vec::new(),
+        };
+        chain.push(env_set_name.clone());
+        for parent_name in &own.extends {
+            env_set.merge(try!(Self::resolve(dirs, parent_name, chain)));
+        }
+        chain.pop();
+
+        env_set.merge(own);
+        Ok(env_set)
+    }
+
+    fn make_table(&self) -> Table {
+        let mut table: Table = self.set
+                                   .iter()
+                                   .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+                                   .collect();
+        table.extend(self.unset.iter().map(|k| (k.clone(), Value::Null)));
+        if !self.extends.is_empty() {
+            let parents = self.extends.iter().map(|name| Value::String(name.to_string())).collect();
+            table.insert(EXTENDS_KEY.to_owned(), Value::Array(parents));
+        }
+        table
+    }
+
+    /// `path`の`EnvSet`ファイルに`content`を書き込んだ場合に、`EnvSet`として読み込めるかを検査する。
+    pub fn validate(env_set_name: &EnvSetName, path: &path::Path, content: &str) -> Result<()> {
+        Self::parse_content(env_set_name, path, content).map(|_| ())
+    }
+
+    fn read_env_set_file(env_set_name: &EnvSetName, path: &path::Path) -> Result<EnvSetContent> {
+        let content: String = try!(Self::read_file(path));
+        Self::parse_content(env_set_name, path, &content)
+    }
+
+    fn parse_content(env_set_name: &EnvSetName,
+                     path: &path::Path,
+                     content: &str)
+                     -> Result<EnvSetContent> {
+        let table: Table = try!(Self::storage_of(path).load(&content).map_err(|e| {
+            match e {
+                LoadError::Syntax(line, col, message) => {
+                    Error::Syntax(SyntaxError::new(env_set_name, path, content, line, col, message))
+                }
+                LoadError::NotMapping => {
+                    Error::NotMapping(env_set_name.clone(), path.to_path_buf())
+                }
+                LoadError::Unsupported(message) => {
+                    Error::UnsupportedContent(path.to_path_buf(), message)
+                }
+            }
+        }));
+        let content = EnvSetContent {
+            extends: try!(Self::make_extends(&table, path)),
+            set: try!(Self::make_set(&table, path)),
+            unset: Self::make_unset(&table),
+        };
+
+        let invalid_unset = content.unset.iter().filter(|k| check_name(k).is_some()).cloned();
+        let invalid_keys: Vec<String> = content.set
+                                               .iter()
+                                               .filter(|&(k, v)| {
+                                                   check_name(k).is_some() ||
+                                                   check_value(v).is_some()
+                                               })
+                                               .map(|(k, _)| k.clone())
+                                               .chain(invalid_unset)
+                                               .collect();
+        if invalid_keys.is_empty() {
+            Ok(content)
+        } else {
+            Err(Error::InvalidVariables(path.to_path_buf(), invalid_keys))
+        }
+    }
+
+    /// `extends`には単一の`EnvSet`名、または`EnvSet`名のリストを指定できる。
+    fn make_extends(orig: &Table, path: &path::Path) -> Result<Vec<EnvSetName>> {
+        let names: Vec<&Value> = match orig.get(EXTENDS_KEY) {
+            Some(&Value::Array(ref names)) => names.iter().collect(),
+            Some(name) => vec![name],
+            None => Vec::new(),
+        };
+        names.into_iter()
+             .map(|name| {
+                 match *name {
+                     Value::String(ref s) => EnvSetName::new(s),
+                     _ => None,
+                 }
+                 .ok_or_else(|| Error::InvalidEnvSetName(path.to_path_buf(), describe_value(name)))
+             })
+             .collect()
+    }
+
+    /// 数値と真偽値は文字列に変換する。リストや対応表は環境変数の値にできないのでエラーとする。
+    fn make_set(orig: &Table, path: &path::Path) -> Result<BTreeMap<String, String>> {
         let mut set = BTreeMap::new();
-        for (k, v) in orig.iter() {
-            k.as_str()
-             .and_then(|k_| v.as_str().and_then(|v_| set.insert(k_.to_owned(), v_.to_owned())));
+        for (k, v) in orig.iter().filter(|&(k, _)| k != EXTENDS_KEY) {
+            let value = match *v {
+                Value::String(ref s) | Value::Float(ref s) => s.clone(),
+                Value::Integer(i) => i.to_string(),
+                Value::Boolean(b) => b.to_string(),
+                Value::Null => continue,
+                Value::Array(_) | Value::Table(_) => {
+                    return Err(Error::NonScalarValue(path.to_path_buf(), k.clone()))
+                }
+            };
+            set.insert(k.clone(), value);
         }
-        set
+        Ok(set)
+    }
+
+    fn make_unset(orig: &Table) -> BTreeSet<String> {
+        orig.iter()
+            .filter(|&(_, v)| *v == Value::Null)
+            .map(|(k, _)| k.clone())
+            .collect()
     }
 
-    fn read_file_content<P: AsRef<path::Path>>(yaml_path: P) -> Result<String> {
-        let mut yaml_file = try!(fs::File::open(&yaml_path));
+    fn read_file_content<P: AsRef<path::Path>>(path: P) -> Result<String> {
+        let mut file = try!(fs::File::open(&path));
         let mut buf = String::new();
-        try!(yaml_file.read_to_string(&mut buf));
+        try!(file.read_to_string(&mut buf));
         Ok(buf)
     }
 }
 
+pub fn is_encrypted_file<P: AsRef<path::Path>>(path: P) -> bool {
+    path.as_ref().extension().map_or(false, |ext| ext == ENCRYPTED_EXTENSION)
+}
+
+/// `foo.yaml`に対して`foo.yaml.enc`を返す。
+pub fn encrypted_path<P: AsRef<path::Path>>(path: P) -> path::PathBuf {
+    let mut path = path.as_ref().as_os_str().to_os_string();
+    path.push(".");
+    path.push(ENCRYPTED_EXTENSION);
+    path::PathBuf::from(path)
+}
+
+/// `foo.yaml.enc`に対して`foo.yaml`を返す。暗号化する拡張子でない場合はそのまま返す。
+pub fn decrypted_path<P: AsRef<path::Path>>(path: P) -> path::PathBuf {
+    if is_encrypted_file(&path) {
+        path.as_ref().with_extension("")
+    } else {
+        path.as_ref().to_path_buf()
+    }
+}
+
+/// エラーメッセージのために、値を書かれた形に近い文字列にする。
+fn describe_value(value: &Value) -> String {
+    match *value {
+        Value::String(ref s) | Value::Float(ref s) => s.clone(),
+        Value::Integer(i) => i.to_string(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Array(_) => "(a sequence)".to_owned(),
+        Value::Table(_) => "(a mapping)".to_owned(),
+        Value::Null => "null".to_owned(),
+    }
+}
+
+/// 環境変数名として使えない場合はその理由を返す。
+///
+/// `=`やNULを含む名前は`Command::env`に渡すとpanicや分かりにくいエラーになる。
+/// `extends`は継承元の指定と区別できないので使えない。
+pub fn check_name(key: &str) -> Option<&'static str> {
+    if key.is_empty() {
+        Some("empty variable name")
+    } else if key == EXTENDS_KEY {
+        Some("variable name is reserved for extends")
+    } else if key.contains('=') {
+        Some("variable name contains '='")
+    } else if key.contains('\0') {
+        Some("variable name contains NUL")
+    } else {
+        None
+    }
+}
+
+/// シェルの変数名として使える`[A-Za-z_][A-Za-z0-9_]*`の形式かを返す。
+pub fn is_portable_name(key: &str) -> bool {
+    let mut chars = key.chars();
+    match chars.next() {
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => {
+            chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+        }
+        _ => false,
+    }
+}
+
+/// `is_portable_name`でない場合はエラーを返す。引用できない形式で出力する前に検査する。
+pub fn check_portable_name(key: &str) -> Result<()> {
+    if is_portable_name(key) {
+        Ok(())
+    } else {
+        Err(Error::UnsupportedName(key.to_owned(), "not a shell variable name".to_owned()))
+    }
+}
+
+/// 環境変数の値として使えない場合はその理由を返す。
+pub fn check_value(value: &str) -> Option<&'static str> {
+    if value.contains('\0') {
+        Some("value contains NUL")
+    } else {
+        None
+    }
+}
+
 pub type EnvSetIterator<'a> = btree_map::Iter<'a, String, String>;
 
 impl<'a> IntoIterator for &'a EnvSet {
@@ -219,6 +670,76 @@ impl<'a> IntoIterator for &'a EnvSet {
     }
 }
 
-fn yaml_str(s: &str) -> yaml::Yaml {
-    yaml::Yaml::String(s.to_owned())
+#[cfg(test)]
+mod tests {
+    use super::{EnvSet, EnvSetName};
+    use error::Error;
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::process;
+
+    fn make_dir(test_name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir: PathBuf = env::temp_dir().join(format!("envars-{}-{}", test_name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for &(name, content) in files {
+            fs::File::create(dir.join(name)).unwrap().write_all(content.as_bytes()).unwrap();
+        }
+        dir
+    }
+
+    fn name(name: &str) -> EnvSetName {
+        EnvSetName::new(name).unwrap()
+    }
+
+    #[test]
+    fn test_resolve_extends() {
+        let dir = make_dir("resolve-extends",
+                           &[("base.yaml", "A: base\nB: base\nC: base\n"),
+                             ("db.yaml", "B: db\nC: db\n"),
+                             ("app.yaml", "extends: [base, db]\nC: app\nA: ~\n")]);
+        let env_set = EnvSet::new(&[dir.clone()], &name("app")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // 後の継承元ほど優先され、子の指定は常に優先される。
+        assert_eq!(env_set.env("A"), None);
+        assert!(env_set.is_unset("A"));
+        assert_eq!(env_set.env("B"), Some(&"db".to_owned()));
+        assert_eq!(env_set.env("C"), Some(&"app".to_owned()));
+    }
+
+    #[test]
+    fn test_lineage() {
+        let dir = make_dir("lineage",
+                           &[("base.yaml", ""),
+                             ("db.yaml", "extends: [base]\n"),
+                             ("app.yaml", "extends: [db, base]\n"),
+                             ("other.yaml", "")]);
+        let app = EnvSet::new(&[dir.clone()], &name("app")).unwrap();
+        let mut merged = EnvSet::new(&[dir.clone()], &name("other")).unwrap();
+        merged.merge(app.clone());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(app.lineage(), &[name("base"), name("db"), name("app")]);
+        assert_eq!(merged.lineage(), &[name("other"), name("base"), name("db"), name("app")]);
+    }
+
+    #[test]
+    fn test_resolve_cycle() {
+        let dir = make_dir("resolve-cycle",
+                           &[("a.yaml", "extends: [b]\n"),
+                             ("b.yaml", "extends: [c]\n"),
+                             ("c.yaml", "extends: [a]\n")]);
+        let result = EnvSet::new(&[dir.clone()], &name("a"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        match result {
+            Err(Error::InheritanceCycle(chain)) => {
+                assert_eq!(chain, vec![name("a"), name("b"), name("c"), name("a")]);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
 }
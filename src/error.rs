@@ -20,34 +20,227 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use envset::EnvSetName;
 use std::error;
 use std::fmt;
 use std::io;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::result;
+use toml;
 use yaml_rust as yaml;
 
 #[derive(Debug)]
 pub enum Error {
     IO(io::Error),
-    LoadYaml(yaml::ScanError),
     EmitYaml(yaml::EmitError),
+    EmitToml(toml::ser::Error),
     ConfigDirIsNotFound,
+    DataDirIsNotFound,
     EnvSetExistsWhenInitializing,
     ProcessFail(process::ExitStatus),
+    InvalidEnvSetName(PathBuf, String),
+    InheritanceCycle(Vec<EnvSetName>),
+    UndefinedVariables(Vec<String>),
+    InterpolationCycle(Vec<String>),
+    NoEnvSetSpecified,
+    ParseDotenv(usize, String),
+    UnsupportedValue(String, String),
+    UnsupportedName(String, String),
+    NonScalarValue(PathBuf, String),
+    InvalidVariables(PathBuf, Vec<String>),
+    InvalidSchema(PathBuf, String),
+    KeyFileNotFound(PathBuf),
+    InvalidKeyFile(PathBuf),
+    DecryptFailed(String),
+    EncryptFailed,
+    VariableNotFound(EnvSetName, String),
+    /// スキーマを持つ`EnvSet`ごとの、規則に反する変数名と説明の組。
+    SchemaViolations(Vec<(EnvSetName, Vec<(String, String)>)>),
+    Syntax(SyntaxError),
+    NotMapping(EnvSetName, PathBuf),
+    UnsupportedContent(PathBuf, String),
+    LintFailed(usize),
+    EnvSetNotFound(EnvSetName),
+    EnvSetExists(EnvSetName),
+    Canceled,
+}
+
+/// `EnvSet`ファイルの構文の誤りと、その位置。
+#[derive(Debug)]
+pub struct SyntaxError {
+    pub env_set: EnvSetName,
+    pub path: PathBuf,
+    /// 1から数える行番号。
+    pub line: usize,
+    /// 1から数える列番号。
+    pub col: usize,
+    pub message: String,
+    /// 誤りのある行の内容。
+    pub source_line: String,
+}
+
+impl SyntaxError {
+    pub fn new(env_set: &EnvSetName,
+               path: &Path,
+               content: &str,
+               line: usize,
+               col: usize,
+               message: String)
+               -> SyntaxError {
+        SyntaxError {
+            env_set: env_set.clone(),
+            path: path.to_path_buf(),
+            line: line,
+            col: col,
+            message: message,
+            source_line: content.lines().nth(line.saturating_sub(1)).unwrap_or("").to_owned(),
+        }
+    }
+}
+
+/// 位置と、誤りのある行に列を指す`^`を添えて表示する。
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let gutter = " ".repeat(self.line.to_string().len());
+        let indent: String = self.source_line
+                                 .chars()
+                                 .take(self.col.saturating_sub(1))
+                                 .map(|c| if c == '\t' { '\t' } else { ' ' })
+                                 .collect();
+        try!(writeln!(f,
+                      "Syntax error in EnvSet {} ({}:{}:{}): {}",
+                      self.env_set,
+                      self.path.display(),
+                      self.line,
+                      self.col,
+                      self.message));
+        try!(writeln!(f, "{} |", gutter));
+        try!(writeln!(f, "{} | {}", self.line, self.source_line));
+        write!(f, "{} | {}^", gutter, indent)
+    }
+}
+
+impl Error {
+    /// envarsのプロセスの終了コードとして用いる値を返す。
+    /// 子プロセスが失敗した場合は`status_exit_code`で求めた値、それ以外のエラーでは1となる。
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            Error::ProcessFail(ref status) => status_exit_code(status),
+            _ => 1,
+        }
+    }
+}
+
+/// 子プロセスの終了状態を終了コードに変換する。
+/// シグナルで終了した場合はシェルと同様に128+シグナル番号とする。
+#[cfg(unix)]
+pub fn status_exit_code(status: &process::ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+
+    status.code().or_else(|| status.signal().map(|signal| 128 + signal)).unwrap_or(1)
+}
+
+/// 子プロセスの終了状態を終了コードに変換する。
+#[cfg(not(unix))]
+pub fn status_exit_code(status: &process::ExitStatus) -> i32 {
+    status.code().unwrap_or(1)
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::IO(ref e) => write!(f, "IO Error ({})", e),
-            Error::LoadYaml(ref e) => write!(f, "Yaml Scan Error ({})", e),
             Error::EmitYaml(ref e) => write!(f, "Yaml Emit Error ({:?})", e),
+            Error::EmitToml(ref e) => write!(f, "Toml Emit Error ({})", e),
             Error::ConfigDirIsNotFound => write!(f, "Config dir is not found"),
+            Error::DataDirIsNotFound => {
+                write!(f, "Data dir for the key file is not found (set $ENVARS_KEY_FILE)")
+            }
             Error::EnvSetExistsWhenInitializing => {
                 write!(f, "EnvSet file exists when initializing")
             }
             Error::ProcessFail(ref status) => write!(f, "Process failed ({})", status),
+            Error::InvalidEnvSetName(ref path, ref name) => {
+                write!(f, "Invalid EnvSet name {} in extends ({})", name, path.display())
+            }
+            Error::InheritanceCycle(ref chain) => {
+                let names: Vec<&str> = chain.iter().map(|name| &**name).collect();
+                write!(f, "EnvSet inheritance cycle ({})", names.join(" -> "))
+            }
+            Error::UndefinedVariables(ref names) => {
+                write!(f, "Undefined variables ({})", names.join(", "))
+            }
+            Error::InterpolationCycle(ref names) => {
+                write!(f, "Variable reference cycle ({})", names.join(" -> "))
+            }
+            Error::NoEnvSetSpecified => write!(f, "No EnvSet is specified"),
+            Error::ParseDotenv(line, ref message) => {
+                write!(f, "Dotenv Parse Error ({} at line {})", message, line)
+            }
+            Error::UnsupportedValue(ref key, ref reason) => {
+                write!(f, "Unsupported value of {} ({})", key, reason)
+            }
+            Error::UnsupportedName(ref key, ref reason) => {
+                write!(f, "Unsupported variable name {} ({})", key, reason)
+            }
+            Error::NonScalarValue(ref path, ref key) => {
+                write!(f,
+                       "The value of {} is not a string, number or boolean ({})",
+                       key,
+                       path.display())
+            }
+            Error::KeyFileNotFound(ref path) => {
+                write!(f,
+                       "Key file is not found: {} (it is created by `envars encrypt`)",
+                       path.display())
+            }
+            Error::InvalidKeyFile(ref path) => write!(f, "Invalid key file: {}", path.display()),
+            Error::DecryptFailed(ref key) => {
+                write!(f, "Failed to decrypt {} (wrong key file or broken value)", key)
+            }
+            Error::EncryptFailed => write!(f, "Failed to encrypt a value"),
+            Error::VariableNotFound(ref env_set, ref key) => {
+                write!(f, "Variable {} is not found in EnvSet {}", key, env_set)
+            }
+            Error::InvalidSchema(ref path, ref message) => {
+                write!(f, "Invalid schema: {} ({})", message, path.display())
+            }
+            Error::SchemaViolations(ref violations) => {
+                for (i, &(ref env_set, ref violations)) in violations.iter().enumerate() {
+                    if i > 0 {
+                        try!(writeln!(f, ""));
+                    }
+                    try!(write!(f, "EnvSet {} violates its schema:", env_set));
+                    for &(ref key, ref message) in violations {
+                        try!(write!(f, "\n  {}: {}", key, message));
+                    }
+                }
+                Ok(())
+            }
+            Error::InvalidVariables(ref path, ref keys) => {
+                let keys: Vec<String> = keys.iter().map(|key| format!("{:?}", key)).collect();
+                write!(f,
+                       "Invalid variable names or values: {} ({})",
+                       keys.join(", "),
+                       path.display())
+            }
+            Error::Syntax(ref e) => write!(f, "{}", e),
+            Error::NotMapping(ref env_set, ref path) => {
+                write!(f,
+                       "EnvSet {} is not a mapping from variable names to values ({})",
+                       env_set,
+                       path.display())
+            }
+            Error::UnsupportedContent(ref path, ref message) => {
+                write!(f, "Unsupported content in EnvSet file ({}: {})", path.display(), message)
+            }
+            Error::LintFailed(count) => write!(f, "{} problem(s) found in EnvSets", count),
+            Error::EnvSetNotFound(ref name) => write!(f, "EnvSet {} is not found", name),
+            Error::EnvSetExists(ref name) => {
+                write!(f, "EnvSet {} already exists (use --force to overwrite)", name)
+            }
+            Error::Canceled => write!(f, "Canceled"),
         }
     }
 }
@@ -58,40 +251,160 @@ impl From<io::Error> for Error {
     }
 }
 
-impl From<yaml::ScanError> for Error {
-    fn from(a: yaml::ScanError) -> Error {
-        Error::LoadYaml(a)
-    }
-}
-
 impl From<yaml::EmitError> for Error {
     fn from(a: yaml::EmitError) -> Error {
         Error::EmitYaml(a)
     }
 }
 
+impl From<toml::ser::Error> for Error {
+    fn from(a: toml::ser::Error) -> Error {
+        Error::EmitToml(a)
+    }
+}
+
 impl error::Error for Error {
     fn description(&self) -> &str {
         match *self {
             Error::IO(ref e) => e.description(),
-            Error::LoadYaml(ref e) => e.description(),
             Error::EmitYaml(_) => "Error in YamlEmitter.",
+            Error::EmitToml(_) => "Error in toml serializer.",
             Error::ConfigDirIsNotFound => "Config dir is not found.",
+            Error::DataDirIsNotFound => "Data dir is not found.",
             Error::EnvSetExistsWhenInitializing => "A EnvSet file exists when Initializing.",
             Error::ProcessFail(_) => "Running child process was fail.",
+            Error::InvalidEnvSetName(_, _) => "Invalid EnvSet name.",
+            Error::InheritanceCycle(_) => "EnvSet inheritance is cyclic.",
+            Error::UndefinedVariables(_) => "Referenced variables are undefined.",
+            Error::InterpolationCycle(_) => "Variable references are cyclic.",
+            Error::NoEnvSetSpecified => "No EnvSet is specified.",
+            Error::ParseDotenv(_, ref message) => message,
+            Error::UnsupportedValue(_, _) => "The value can't be written in the format.",
+            Error::UnsupportedName(_, _) => "The variable name can't be written in the format.",
+            Error::NonScalarValue(_, _) => "A value of the EnvSet is a sequence or mapping.",
+            Error::InvalidVariables(_, _) => {
+                "Some variables of the EnvSet can't be passed to a process."
+            }
+            Error::InvalidSchema(_, _) => "The schema file is invalid.",
+            Error::KeyFileNotFound(_) => "The key file for encrypted values is not found.",
+            Error::InvalidKeyFile(_) => "The key file doesn't contain a valid key.",
+            Error::DecryptFailed(_) => "An encrypted value can't be decrypted.",
+            Error::EncryptFailed => "A value can't be encrypted.",
+            Error::VariableNotFound(_, _) => "The variable is not found in the EnvSet.",
+            Error::SchemaViolations(_) => "The EnvSet violates its schema.",
+            Error::Syntax(ref e) => &e.message,
+            Error::NotMapping(_, _) => "The top level of the EnvSet file is not a mapping.",
+            Error::UnsupportedContent(_, _) => "The EnvSet file contains what can't be an EnvSet.",
+            Error::LintFailed(_) => "Problems are found in EnvSets.",
+            Error::EnvSetNotFound(_) => "The EnvSet file is not found.",
+            Error::EnvSetExists(_) => "The EnvSet file already exists.",
+            Error::Canceled => "The operation is canceled by the user.",
         }
     }
 
     fn cause(&self) -> Option<&error::Error> {
         match *self {
             Error::IO(ref e) => Some(e),
-            Error::LoadYaml(ref e) => Some(e),
+            Error::EmitToml(ref e) => Some(e),
             Error::EmitYaml(_) |
             Error::ConfigDirIsNotFound |
+            Error::DataDirIsNotFound |
             Error::EnvSetExistsWhenInitializing |
-            Error::ProcessFail(_) => None,
+            Error::ProcessFail(_) |
+            Error::InvalidEnvSetName(_, _) |
+            Error::InheritanceCycle(_) |
+            Error::UndefinedVariables(_) |
+            Error::InterpolationCycle(_) |
+            Error::NoEnvSetSpecified |
+            Error::ParseDotenv(_, _) |
+            Error::UnsupportedValue(_, _) |
+            Error::UnsupportedName(_, _) |
+            Error::NonScalarValue(_, _) |
+            Error::InvalidVariables(_, _) |
+            Error::InvalidSchema(_, _) |
+            Error::KeyFileNotFound(_) |
+            Error::InvalidKeyFile(_) |
+            Error::DecryptFailed(_) |
+            Error::EncryptFailed |
+            Error::VariableNotFound(_, _) |
+            Error::SchemaViolations(_) |
+            Error::Syntax(_) |
+            Error::NotMapping(_, _) |
+            Error::UnsupportedContent(_, _) |
+            Error::LintFailed(_) |
+            Error::EnvSetNotFound(_) |
+            Error::EnvSetExists(_) |
+            Error::Canceled => None,
         }
     }
 }
 
 pub type Result<T> = result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, SyntaxError};
+    use envset::EnvSetName;
+    use std::path::Path;
+    use storage::{JsonStorage, LoadError, Storage, TomlStorage, YamlStorage};
+
+    fn syntax_error(storage: &Storage, content: &str) -> SyntaxError {
+        match storage.load(content) {
+            Err(LoadError::Syntax(line, col, message)) => {
+                SyntaxError::new(&EnvSetName::new("app").unwrap(),
+                                 Path::new("app.yaml"),
+                                 content,
+                                 line,
+                                 col,
+                                 message)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_syntax_error_position() {
+        let error = syntax_error(&YamlStorage, "A: 1\nB: [x\n");
+        assert_eq!((error.line, error.col), (3, 1));
+        let error = syntax_error(&YamlStorage, "A: 1\nB: c: d\n");
+        assert_eq!((error.line, error.col), (2, 5));
+        let error = syntax_error(&TomlStorage, "A = 1\nB = \"x\n");
+        assert_eq!(error.line, 2);
+        let error = syntax_error(&JsonStorage, "{\n  \"A\": 1,\n  \"B\" 2\n}");
+        assert_eq!((error.line, error.col), (3, 7));
+        let error = syntax_error(&JsonStorage, "{\"A\" 1}");
+        assert_eq!((error.line, error.col), (1, 6));
+    }
+
+    #[test]
+    fn test_syntax_error_message() {
+        let error = SyntaxError::new(&EnvSetName::new("app").unwrap(),
+                                     Path::new("app.yaml"),
+                                     "A: 1\nB:\t[x\n",
+                                     2,
+                                     5,
+                                     "unclosed sequence".to_owned());
+        assert_eq!(Error::Syntax(error).to_string(),
+                   "Syntax error in EnvSet app (app.yaml:2:5): unclosed sequence\n  |\n2 | \
+                    B:\t[x\n  |   \t ^");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_status_exit_code() {
+        use super::status_exit_code;
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::ExitStatus;
+
+        // `from_raw`は`waitpid`の状態値を受け取る。終了コードは上位のバイトに入る。
+        assert_eq!(status_exit_code(&ExitStatus::from_raw(0)), 0);
+        assert_eq!(status_exit_code(&ExitStatus::from_raw(3 << 8)), 3);
+        assert_eq!(status_exit_code(&ExitStatus::from_raw(255 << 8)), 255);
+        // SIGKILL, SIGTERM
+        assert_eq!(status_exit_code(&ExitStatus::from_raw(9)), 137);
+        assert_eq!(status_exit_code(&ExitStatus::from_raw(15)), 143);
+
+        assert_eq!(Error::ProcessFail(ExitStatus::from_raw(2 << 8)).exit_code(), 2);
+        assert_eq!(Error::NoEnvSetSpecified.exit_code(), 1);
+    }
+}
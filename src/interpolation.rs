@@ -0,0 +1,257 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 Siphilia
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! 値の中の`${NAME}`及び`${NAME:-default}`形式の参照を展開する。
+//!
+//! 参照は同じ集合の変数から優先して解決され、見つからない場合は`fallback`で解決される。
+//! `PATH: "/opt/bin:${PATH}"`のように変数が自身を参照する場合は、`fallback`の値を参照する。
+//! `default`は参照先が未定義か空文字列の場合に用いられる。`$${`と書くと`${`そのものになる。
+//! `literals`に含まれる変数の値は展開せず、そのまま用いる。
+
+use error::{Error, Result};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// `set`の全ての値を展開した新しい集合と、`literals`の変数の値を含む変数名の集合を返す。
+/// `default`の無い参照が解決できなかった場合は、その変数名を全て列挙したエラーを返す。
+pub fn expand<F>(set: &BTreeMap<String, String>,
+                 literals: &BTreeSet<String>,
+                 fallback: F)
+                 -> Result<(BTreeMap<String, String>, BTreeSet<String>)>
+    where F: Fn(&str) -> Option<String>
+{
+    let mut expander = Expander {
+        raw: set,
+        literals: literals,
+        fallback: fallback,
+        resolved: BTreeMap::new(),
+        derived: BTreeSet::new(),
+        stack: Vec::new(),
+        missing: BTreeSet::new(),
+    };
+    for key in set.keys() {
+        try!(expander.resolve(key));
+    }
+    if expander.missing.is_empty() {
+        Ok((expander.resolved, expander.derived))
+    } else {
+        Err(Error::UndefinedVariables(expander.missing.into_iter().collect()))
+    }
+}
+
+struct Expander<'a, F> {
+    raw: &'a BTreeMap<String, String>,
+    literals: &'a BTreeSet<String>,
+    fallback: F,
+    resolved: BTreeMap<String, String>,
+    /// `literals`の変数、またはそれを参照する変数の名前。
+    derived: BTreeSet<String>,
+    /// 展開中の変数名。参照の循環の検出に用いる。
+    stack: Vec<String>,
+    missing: BTreeSet<String>,
+}
+
+impl<'a, F> Expander<'a, F> where F: Fn(&str) -> Option<String>
+{
+    fn resolve(&mut self, name: &str) -> Result<Option<String>> {
+        if let Some(value) = self.resolved.get(name).cloned() {
+            self.propagate(name);
+            return Ok(Some(value));
+        }
+        if self.stack.last().map_or(false, |n| n == name) {
+            return Ok((self.fallback)(name));
+        }
+        if let Some(pos) = self.stack.iter().position(|n| n == name) {
+            let mut cycle: Vec<String> = self.stack[pos..].to_vec();
+            cycle.push(name.to_owned());
+            return Err(Error::InterpolationCycle(cycle));
+        }
+
+        match self.raw.get(name) {
+            Some(raw) if self.literals.contains(name) => {
+                self.resolved.insert(name.to_owned(), raw.clone());
+                self.derived.insert(name.to_owned());
+                self.propagate(name);
+                Ok(Some(raw.clone()))
+            }
+            Some(raw) => {
+                self.stack.push(name.to_owned());
+                let value: String = try!(self.expand_str(raw));
+                self.stack.pop();
+                self.resolved.insert(name.to_owned(), value.clone());
+                self.propagate(name);
+                Ok(Some(value))
+            }
+            None => Ok((self.fallback)(name)),
+        }
+    }
+
+    /// `name`が`literals`由来の値を持つ場合、それを参照している展開中の変数にも印を付ける。
+    fn propagate(&mut self, name: &str) {
+        if self.derived.contains(name) {
+            if let Some(referrer) = self.stack.last() {
+                self.derived.insert(referrer.clone());
+            }
+        }
+    }
+
+    fn expand_str(&mut self, s: &str) -> Result<String> {
+        let mut buf = String::new();
+        let mut rest: &str = s;
+        while let Some(start) = rest.find('$') {
+            buf.push_str(&rest[..start]);
+            rest = &rest[start..];
+            if rest.starts_with("$${") {
+                buf.push_str("${");
+                rest = &rest[3..];
+            } else if rest.starts_with("${") {
+                match find_closing_brace(&rest[2..]) {
+                    Some(end) => {
+                        let value: String = try!(self.expand_reference(&rest[2..2 + end]));
+                        buf.push_str(&value);
+                        rest = &rest[2 + end + 1..];
+                    }
+                    None => break,
+                }
+            } else {
+                buf.push('$');
+                rest = &rest[1..];
+            }
+        }
+        buf.push_str(rest);
+        Ok(buf)
+    }
+
+    /// `${`と`}`の間の`NAME`または`NAME:-default`を展開する。
+    fn expand_reference(&mut self, reference: &str) -> Result<String> {
+        let (name, default) = match reference.find(":-") {
+            Some(pos) => (&reference[..pos], Some(&reference[pos + 2..])),
+            None => (reference, None),
+        };
+        match (try!(self.resolve(name)), default) {
+            (Some(ref value), Some(default)) if value.is_empty() => self.expand_str(default),
+            (Some(value), _) => Ok(value),
+            (None, Some(default)) => self.expand_str(default),
+            (None, None) => {
+                self.missing.insert(name.to_owned());
+                Ok(String::new())
+            }
+        }
+    }
+}
+
+/// 入れ子になった`${...}`を考慮して、対応する`}`の位置を返す。
+fn find_closing_brace(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::expand;
+    use error::Error;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    fn set(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|&(k, v)| (k.to_owned(), v.to_owned())).collect()
+    }
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    fn no_literals() -> BTreeSet<String> {
+        BTreeSet::new()
+    }
+
+    #[test]
+    fn test_expand_references() {
+        let orig = set(&[("DB_HOST", "localhost"),
+                         ("DB_PORT", "${PORT:-5432}"),
+                         ("DATABASE_URL", "postgres://${DB_HOST}:${DB_PORT}/${USER}"),
+                         ("LITERAL", "$${DB_HOST} costs $5")]);
+        let expanded = expand(&orig, &no_literals(), |name| {
+                           if name == "USER" {
+                               Some("alice".to_owned())
+                           } else {
+                               None
+                           }
+                       })
+                           .unwrap()
+                           .0;
+        assert_eq!(expanded["DATABASE_URL"], "postgres://localhost:5432/alice");
+        assert_eq!(expanded["LITERAL"], "${DB_HOST} costs $5");
+    }
+
+    #[test]
+    fn test_expand_undefined() {
+        let orig = set(&[("A", "${X}${Y:-y}"), ("B", "${Z}")]);
+        match expand(&orig, &no_literals(), no_env) {
+            Err(Error::UndefinedVariables(names)) => assert_eq!(names, vec!["X", "Z"]),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_expand_cycle() {
+        let orig = set(&[("A", "${B}"), ("B", "${A}")]);
+        match expand(&orig, &no_literals(), no_env) {
+            Err(Error::InterpolationCycle(names)) => assert_eq!(names, vec!["A", "B", "A"]),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_expand_self_reference() {
+        let orig = set(&[("PATH", "/opt/bin:${PATH}"), ("EDITOR", "${EDITOR:-vi}")]);
+        let expanded = expand(&orig, &no_literals(), |name| {
+                           if name == "PATH" {
+                               Some("/usr/bin".to_owned())
+                           } else {
+                               None
+                           }
+                       })
+                           .unwrap()
+                           .0;
+        assert_eq!(expanded["PATH"], "/opt/bin:/usr/bin");
+        assert_eq!(expanded["EDITOR"], "vi");
+    }
+
+    #[test]
+    fn test_expand_literals() {
+        let orig = set(&[("PASSWORD", "p$${x}${Y}"),
+                         ("URL", "db://u:${PASSWORD}@host"),
+                         ("HOST", "host")]);
+        let literals = ["PASSWORD".to_owned()].iter().cloned().collect();
+        let (expanded, derived) = expand(&orig, &literals, no_env).unwrap();
+        assert_eq!(expanded["PASSWORD"], "p$${x}${Y}");
+        assert_eq!(expanded["URL"], "db://u:p$${x}${Y}@host");
+        assert_eq!(derived.into_iter().collect::<Vec<_>>(), vec!["PASSWORD", "URL"]);
+    }
+}
@@ -27,34 +27,83 @@
 #![cfg_attr(feature="sorty", plugin(sorty))]
 #![cfg_attr(feature="sorty", warn(unsorted_declarations))]
 
+extern crate aes_gcm;
+extern crate base64;
+#[cfg(unix)]
+extern crate libc;
+extern crate regex;
 extern crate s_app_dir;
+extern crate serde;
+extern crate serde_json;
+extern crate toml;
 extern crate yaml_rust;
 
 pub mod cmdargs;
 pub mod config;
+pub mod dotenv;
+pub mod dump;
 pub mod envset;
 pub mod error;
+pub mod interpolation;
+pub mod lint;
+pub mod schema;
+pub mod secret;
+pub mod shell;
+pub mod signal;
+pub mod storage;
 
-use cmdargs::CmdArgs;
+use cmdargs::{CmdArgs, CopyArgs, DeleteArgs, DumpArgs, EncryptArgs, ExportArgs, GetArgs, GlobalArgs,
+              ImportArgs, LintArgs, NewArgs, RunArgs, SetArgs, ShowArgs, UnsetArgs};
 use envset::{EnvSet, EnvSetName};
 use error::{Error, Result};
+use schema::Schema;
+use secret::{Key, PrivateTempFile};
+use serde_json::Value as Json;
+use std::collections::BTreeMap;
 use std::env;
+use std::ffi::OsString;
 use std::fs;
+use std::io;
+use std::io::{Read, Write};
 use std::path;
 use std::process;
 use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use storage::Storage;
 
-pub fn start(mode: &mut CmdArgs) -> Result<()> {
-    try!(config::create_config_dir());
+pub fn start(global: &GlobalArgs, mode: &mut CmdArgs) -> Result<()> {
+    let dirs: Vec<path::PathBuf> = try!(config::env_set_dirs(&global.config_dirs));
+    try!(config::create_default_env_set_dir(&dirs));
     match *mode {
-        CmdArgs::Edit(ref env_set) => edit(env_set),
-        CmdArgs::List => list(),
-        CmdArgs::New(ref env_set) => new(env_set),
-        CmdArgs::Run(ref env_set, ref mut cmd) => run(env_set, cmd),
+        CmdArgs::Copy(ref args) => copy(&dirs, args, false),
+        CmdArgs::Decrypt(ref env_set) => decrypt(&dirs, env_set),
+        CmdArgs::Delete(ref args) => delete(&dirs, args),
+        CmdArgs::Dump(ref args) => dump(&dirs, args),
+        CmdArgs::Edit(ref env_set) => edit(&dirs, env_set),
+        CmdArgs::Encrypt(ref args) => encrypt(&dirs, args),
+        CmdArgs::Exec(ref mut args) => exec(&dirs, args),
+        CmdArgs::Export(ref args) => export(&dirs, args),
+        CmdArgs::Get(ref args) => get(&dirs, args),
+        CmdArgs::Import(ref args) => import(&dirs, args),
+        CmdArgs::Lint(ref args) => lint(&dirs, args),
+        CmdArgs::List => list(&dirs),
+        CmdArgs::Move(ref args) => copy(&dirs, args, true),
+        CmdArgs::New(ref args) => new(&dirs, args),
+        CmdArgs::Run(ref mut args) => run(&dirs, args),
+        CmdArgs::Set(ref args) => set(&dirs, args),
+        CmdArgs::Show(ref args) => show(&dirs, args),
+        CmdArgs::Unset(ref args) => unset(&dirs, args),
         _ => help(), // Show help.
     }
 }
 
+/// `--clean`の時にも常に引き継ぐ環境変数。
+#[cfg(windows)]
+const DEFAULT_KEPT_VARS: &'static [&'static str] = &["PATH", "SYSTEMROOT", "USERPROFILE"];
+
+#[cfg(not(windows))]
+const DEFAULT_KEPT_VARS: &'static [&'static str] = &["PATH", "HOME"];
+
 #[cfg(windows)]
 fn default_editor() -> String {
     "notepad".to_owned()
@@ -65,63 +114,181 @@ fn default_editor() -> String {
     "vi".to_owned()
 }
 
-fn edit(env_set_name: &EnvSetName) -> Result<()> {
-    let path: path::PathBuf = try!(EnvSet::yaml_file_path(&env_set_name));
+fn edit(dirs: &[path::PathBuf], env_set_name: &EnvSetName) -> Result<()> {
+    let path: path::PathBuf = try!(EnvSet::find_file_path(dirs, &env_set_name));
+    if envset::is_encrypted_file(&path) {
+        return edit_encrypted(env_set_name, &path);
+    }
+    try!(open_editor(&path));
+    Ok(())
+}
+
+/// 復号した内容を一時ファイルに書き出してエディタで開き、エディタが正常に終了したら暗号化して保存する。
+/// 編集した内容が`EnvSet`として読み込めない場合は、もう一度編集するかを尋ねる。
+/// 一時ファイルは保存の成否に関わらず削除される。
+fn edit_encrypted(env_set_name: &EnvSetName, path: &path::Path) -> Result<()> {
+    let content: String = try!(EnvSet::read_file(path));
+    let file_name = envset::decrypted_path(path)
+                        .file_name()
+                        .map(|name| name.to_os_string())
+                        .unwrap_or_default();
+    let temp: PrivateTempFile = try!(PrivateTempFile::create(&file_name, &content));
+
+    let edited: String = loop {
+        let exit_status: process::ExitStatus = try!(open_editor(temp.path()));
+        if !exit_status.success() {
+            return Err(Error::ProcessFail(exit_status));
+        }
+        let edited: String = try!(temp.read());
+        match EnvSet::validate(env_set_name, path, &edited) {
+            Ok(()) => break edited,
+            Err(error) => {
+                eprintln!("{}", error);
+                if !try!(confirm("Edit again? (the changes are discarded otherwise)")) {
+                    return Err(Error::Canceled);
+                }
+            }
+        }
+    };
+    if edited != content {
+        try!(EnvSet::write_file(path, &edited));
+    }
+    Ok(())
+}
+
+/// エディタの終了を待つ間、envarsが受け取ったシグナルはエディタに転送される。
+fn open_editor(path: &path::Path) -> Result<process::ExitStatus> {
     let editor = env::var("EDITOR").unwrap_or_else(|_| default_editor());
 
     let mut cmd = Command::new(&editor);
-    cmd.arg(&path);
+    cmd.arg(path);
     cmd.stdin(process::Stdio::inherit());
     cmd.stdout(process::Stdio::inherit());
     cmd.stderr(process::Stdio::inherit());
 
-    let mut handle: process::Child = try!(cmd.spawn());
-    try!(handle.wait());
+    Ok(try!(signal::spawn_and_wait(&mut cmd)))
+}
+
+/// `EnvSet`ファイルを検査し、問題が見つかった場合はエラーとする。
+fn lint(dirs: &[path::PathBuf], args: &LintArgs) -> Result<()> {
+    let env_set_names: Vec<EnvSetName> = if args.env_sets.is_empty() {
+        try!(EnvSet::names(dirs))
+    } else {
+        args.env_sets.clone()
+    };
+    let problems: Vec<lint::Problem> = env_set_names.iter()
+                                                    .flat_map(|name| lint::lint(dirs, name))
+                                                    .collect();
+
+    if args.json {
+        println!("{:#}", Json::Array(problems.iter().map(lint::Problem::to_json).collect()));
+    } else {
+        for problem in &problems {
+            let severity = match problem.severity {
+                lint::Severity::Error => "",
+                lint::Severity::Warning => "warning: ",
+            };
+            match problem.key {
+                Some(ref key) => {
+                    println!("{}: {}: {}{}", problem.env_set, key, severity, problem.message)
+                }
+                None => println!("{}: {}{}", problem.env_set, severity, problem.message),
+            }
+        }
+    }
 
+    let errors: usize = problems.iter()
+                                .filter(|problem| problem.severity == lint::Severity::Error)
+                                .count();
+    if errors == 0 {
+        Ok(())
+    } else {
+        Err(Error::LintFailed(errors))
+    }
+}
+
+/// ディレクトリ毎に`EnvSet`ファイルを表示する。
+fn list(dirs: &[path::PathBuf]) -> Result<()> {
+    for line in try!(list_lines(dirs)) {
+        println!("{}", line);
+    }
     Ok(())
 }
 
-fn list() -> Result<()> {
-    let config_dir: path::PathBuf = try!(config::config_dir());
-    println!("{}:", config_dir.to_str().unwrap_or("$XDG_CONFIG_HOME/envars"));
-    for i in try!(fs::read_dir(&config_dir)) {
-        let entry: fs::DirEntry = try!(i);
-        if let Some(name) = entry.file_name().to_str() {
-            println!("  {}", name);
+/// `list`で表示する行を返す。
+/// より優先されるディレクトリにある同じ名前の`EnvSet`で隠されているものにはその旨を添える。
+fn list_lines(dirs: &[path::PathBuf]) -> Result<Vec<String>> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut found: BTreeMap<EnvSetName, path::PathBuf> = BTreeMap::new();
+    for dir in dirs {
+        if !dir.is_dir() {
+            continue;
+        }
+        lines.push(format!("{}:", dir.display()));
+        for name in try!(EnvSet::names_in(dir)) {
+            let path: path::PathBuf = match EnvSet::find_file_path_in(dir, &name) {
+                Some(path) => path,
+                None => continue,
+            };
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            match found.get(&name) {
+                Some(shadowing) => {
+                    lines.push(format!("  {} (shadowed by {})", file_name, shadowing.display()))
+                }
+                None => lines.push(format!("  {}", file_name)),
+            }
+            found.entry(name).or_insert(path);
         }
     }
-    Ok(())
+    Ok(lines)
 }
 
-fn new(env_set_name: &EnvSetName) -> Result<()> {
-    if try!(EnvSet::does_env_set_exists(&env_set_name)) {
+fn new(dirs: &[path::PathBuf], args: &NewArgs) -> Result<()> {
+    if try!(EnvSet::does_env_set_exists(dirs, &args.env_set)) {
         Err(Error::EnvSetExistsWhenInitializing)
     } else {
-        init_env_set_file(&env_set_name)
+        init_env_set_file(dirs, &args.env_set, args.storage)
+    }
+}
+
+/// dotenv形式のファイルを読み込み、新しい`EnvSet`ファイルとして保存する。
+fn import(dirs: &[path::PathBuf], args: &ImportArgs) -> Result<()> {
+    if try!(EnvSet::does_env_set_exists(dirs, &args.env_set)) {
+        return Err(Error::EnvSetExistsWhenInitializing);
     }
+
+    let mut content = String::new();
+    try!(try!(fs::File::open(&args.from)).read_to_string(&mut content));
+    let mut env_set: EnvSet = try!(EnvSet::empty(dirs, &args.env_set));
+    let vars: Vec<(String, String)> = try!(dotenv::parse(&content)).into_iter().collect();
+    try!(check_vars(&env_set, &vars));
+    for (k, v) in vars {
+        env_set.set_env(k, v);
+    }
+    try!(env_set.write_to_file());
+    Ok(())
 }
 
 /// 記述方法を例示する内容で `EnvSet` ファイルを初期化する。
-fn init_env_set_file(env_set_name: &EnvSetName) -> Result<()> {
-    let mut env_set: EnvSet = try!(EnvSet::empty(&env_set_name));
+fn init_env_set_file(dirs: &[path::PathBuf],
+                     env_set_name: &EnvSetName,
+                     storage: &Storage)
+                     -> Result<()> {
+    let mut env_set: EnvSet = try!(EnvSet::empty_in(dirs, &env_set_name, storage));
     env_set.set_env("VarName".to_owned(), "VarValue".to_owned());
     try!(env_set.write_to_file());
     Ok(())
 }
 
 /// `EnvSet`の読み込み～環境変数の設定～指定コマンドの実行、を行う。
-fn run(env_set_name: &EnvSetName, cmd: &mut Command) -> Result<()> {
-    let env_set: EnvSet = try!(EnvSet::new(&env_set_name));
-
-    for (k, v) in env_set.iter() {
-        cmd.env(k, v);
-    }
+fn run(dirs: &[path::PathBuf], args: &mut RunArgs) -> Result<()> {
+    try!(prepare_command(dirs, args));
+    let cmd: &mut Command = &mut args.cmd;
     cmd.stdin(process::Stdio::inherit());
     cmd.stdout(process::Stdio::inherit());
     cmd.stderr(process::Stdio::inherit());
 
-    let mut handle: process::Child = try!(cmd.spawn());
-    let exit_status: process::ExitStatus = try!(handle.wait());
+    let exit_status: process::ExitStatus = try!(signal::spawn_and_wait(cmd));
 
     if exit_status.success() {
         Ok(())
@@ -130,21 +297,765 @@ fn run(env_set_name: &EnvSetName, cmd: &mut Command) -> Result<()> {
     }
 }
 
+/// `run`と同様に環境変数を設定し、envarsのプロセスを指定コマンドで置き換える。
+/// 成功した場合は戻らない。
+#[cfg(unix)]
+fn exec(dirs: &[path::PathBuf], args: &mut RunArgs) -> Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    try!(prepare_command(dirs, args));
+    Err(Error::IO(args.cmd.exec()))
+}
+
+/// プロセスを置き換えられない環境では`run`と同じ動作となる。
+#[cfg(not(unix))]
+fn exec(dirs: &[path::PathBuf], args: &mut RunArgs) -> Result<()> {
+    run(dirs, args)
+}
+
+/// `EnvSet`を読み込み、指定コマンドに環境変数を設定する。
+fn prepare_command(dirs: &[path::PathBuf], args: &mut RunArgs) -> Result<()> {
+    let mut env_set: EnvSet = try!(load_env_sets(dirs, &args.env_sets, args.verbose));
+    try!(decrypt_values(&mut env_set));
+    try!(env_set.interpolate());
+    try!(check_schemas(dirs, &env_set));
+
+    let cmd: &mut Command = &mut args.cmd;
+    if args.clean {
+        cmd.env_clear();
+        let kept = DEFAULT_KEPT_VARS.iter().cloned().chain(args.keep.iter().map(|k| k.as_str()));
+        for k in kept {
+            if let Some(v) = env::var_os(k) {
+                cmd.env(k, v);
+            }
+        }
+    }
+    for k in env_set.unset_vars() {
+        cmd.env_remove(k);
+    }
+    for (k, v) in env_set.iter() {
+        cmd.env(k, v);
+    }
+    Ok(())
+}
+
+/// `EnvSet`ファイルを同じ形式で複製する。`remove_src`の場合は名前を変更する。
+/// スキーマファイルがあればそれも複製する。ファイルの権限は保たれる。
+/// 上書きする場合は置き換える`dst`と同じディレクトリに、そうでなければ`src`と同じディレクトリに作る。
+///
+/// 上書きする場合も、複製したファイルを一時的な名前で用意してから置き換えるので、
+/// 途中で失敗しても元の`dst`は失われない。
+fn copy(dirs: &[path::PathBuf], args: &CopyArgs, remove_src: bool) -> Result<()> {
+    if args.src == args.dst {
+        return Ok(());
+    }
+    let src_path: path::PathBuf = try!(EnvSet::find_file_path(dirs, &args.src));
+    if !src_path.is_file() {
+        return Err(Error::EnvSetNotFound(args.src.clone()));
+    }
+    // 既存の`dst`は優先順位の高いものを置き換え、無ければ`src`と同じディレクトリに作る。
+    let existing_dst: path::PathBuf = try!(EnvSet::find_file_path(dirs, &args.dst));
+    let overwrite: bool = existing_dst.is_file();
+    if overwrite && !args.force {
+        return Err(Error::EnvSetExists(args.dst.clone()));
+    }
+    let src_dir: path::PathBuf = src_path.parent().map(|dir| dir.to_path_buf()).unwrap_or_default();
+    let dst_dir: path::PathBuf = if overwrite {
+        existing_dst.parent().map(|dir| dir.to_path_buf()).unwrap_or_default()
+    } else {
+        src_dir.clone()
+    };
+
+    // `foo.yaml.enc`のような拡張子を保つ。
+    let src_file_name: String = src_path.file_name()
+                                        .unwrap_or_default()
+                                        .to_string_lossy()
+                                        .into_owned();
+    let dst_file_name: String = format!("{}{}", &*args.dst, &src_file_name[args.src.len()..]);
+    let dst_path: path::PathBuf = dst_dir.join(dst_file_name);
+    let src_schema: path::PathBuf = Schema::file_path_in(&src_dir, &args.src);
+    let dst_schema: path::PathBuf = Schema::file_path_in(&dst_dir, &args.dst);
+
+    let mut files: Vec<(path::PathBuf, path::PathBuf)> = vec![(src_path, dst_path.clone())];
+    if src_schema.is_file() {
+        files.push((src_schema, dst_schema.clone()));
+    }
+    // 別のディレクトリにも移動できるよう、移動する場合も複製してから元のファイルを削除する。
+    let mut staged: Vec<(path::PathBuf, path::PathBuf)> = Vec::new();
+    for &(ref src, ref dst) in &files {
+        let temp: path::PathBuf = staging_path(dst);
+        if let Err(error) = fs::copy(src, &temp) {
+            unstage(&staged);
+            return Err(From::from(error));
+        }
+        staged.push((temp, dst.clone()));
+    }
+    for &(ref temp, ref dst) in &staged {
+        try!(fs::rename(temp, dst));
+    }
+    if remove_src {
+        for &(ref src, _) in &files {
+            try!(fs::remove_file(src));
+        }
+    }
+
+    // 置き換えた`dst`の別の形式のファイルや古いスキーマが残っていると、複製したものと混ざってしまう。
+    if overwrite {
+        for path in EnvSet::candidate_paths_in(&dst_dir, &args.dst) {
+            if path != dst_path && path.is_file() {
+                try!(fs::remove_file(path));
+            }
+        }
+        if files.len() == 1 && dst_schema.is_file() {
+            try!(fs::remove_file(&dst_schema));
+        }
+    }
+    Ok(())
+}
+
+/// 置き換える前のファイルを用意する、同じディレクトリの隠しファイルのパス。
+fn staging_path(dst: &path::Path) -> path::PathBuf {
+    let mut file_name = OsString::from(".");
+    file_name.push(dst.file_name().unwrap_or_default());
+    file_name.push(format!(".{}.tmp", process::id()));
+    dst.with_file_name(file_name)
+}
+
+/// 用意したファイルを削除する。
+fn unstage(staged: &[(path::PathBuf, path::PathBuf)]) {
+    for &(ref temp, _) in staged {
+        let _ = fs::remove_file(temp);
+    }
+}
+
+/// `EnvSet`ファイルとスキーマファイルを削除する。一つでも見つからない`EnvSet`があれば何も削除しない。
+/// ファイルの内容は読まないので、壊れた`EnvSet`ファイルも削除できる。
+fn delete(dirs: &[path::PathBuf], args: &DeleteArgs) -> Result<()> {
+    let mut env_sets: Vec<(&EnvSetName, path::PathBuf)> = Vec::new();
+    for env_set_name in &args.env_sets {
+        if env_sets.iter().any(|&(name, _)| name == env_set_name) {
+            continue;
+        }
+        let path: path::PathBuf = try!(EnvSet::find_file_path(dirs, env_set_name));
+        if !path.is_file() {
+            return Err(Error::EnvSetNotFound(env_set_name.clone()));
+        }
+        env_sets.push((env_set_name, path));
+    }
+
+    if !args.yes {
+        for &(_, ref path) in &env_sets {
+            eprintln!("  {}", path.display());
+        }
+        let verb = if args.trash { "Move to trash" } else { "Delete" };
+        if !try!(confirm(&format!("{} {} EnvSet file(s)?", verb, env_sets.len()))) {
+            return Err(Error::Canceled);
+        }
+    }
+
+    for &(env_set_name, ref path) in &env_sets {
+        let dir: &path::Path = path.parent().unwrap_or(path::Path::new(""));
+        let schema_path: path::PathBuf = Schema::file_path_in(dir, env_set_name);
+        if args.trash {
+            let suffix: String = try!(trash_suffix(dir, path, &schema_path));
+            try!(move_to_trash(path, &suffix));
+            if schema_path.is_file() {
+                try!(move_to_trash(&schema_path, &suffix));
+            }
+        } else {
+            try!(fs::remove_file(path));
+            if schema_path.is_file() {
+                try!(fs::remove_file(&schema_path));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 標準エラー出力に質問を表示し、`y`または`yes`が入力された場合に`true`を返す。
+fn confirm(question: &str) -> Result<bool> {
+    eprint!("{} [y/N] ", question);
+    try!(io::stderr().flush());
+    let mut answer = String::new();
+    try!(io::stdin().read_line(&mut answer));
+    let answer = answer.trim().to_lowercase();
+    Ok(answer == "y" || answer == "yes")
+}
+
+/// ゴミ箱のディレクトリ。`EnvSet`ファイルと同じディレクトリに作るので、名前の変更だけで移動できる。
+const TRASH_DIR_NAME: &'static str = ".trash";
+
+/// ゴミ箱に移すファイル名に付ける、削除した時刻の接尾辞を返す。
+/// 同じ時刻に同じ名前のファイルを削除していた場合は、どのファイルも上書きしないよう番号を付け加える。
+fn trash_suffix(dir: &path::Path,
+                env_set_path: &path::Path,
+                schema_path: &path::Path)
+                -> Result<String> {
+    let trash_dir: path::PathBuf = dir.join(TRASH_DIR_NAME);
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let timestamp = format!("{}.{:09}", elapsed.as_secs(), elapsed.subsec_nanos());
+    let mut count: usize = 0;
+    loop {
+        let suffix: String = if count == 0 {
+            timestamp.clone()
+        } else {
+            format!("{}-{}", timestamp, count)
+        };
+        let is_used = [env_set_path, schema_path]
+                          .iter()
+                          .any(|path| trash_dir.join(trash_file_name(path, &suffix)).exists());
+        if !is_used {
+            return Ok(suffix);
+        }
+        count += 1;
+    }
+}
+
+fn trash_file_name(path: &path::Path, suffix: &str) -> OsString {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(format!(".{}", suffix));
+    file_name
+}
+
+/// ファイルと同じディレクトリの`.trash`に、`suffix`を付けた名前で移動する。
+fn move_to_trash(path: &path::Path, suffix: &str) -> Result<()> {
+    let dir: &path::Path = path.parent().unwrap_or(path::Path::new(""));
+    let trash_dir: path::PathBuf = dir.join(TRASH_DIR_NAME);
+    try!(fs::create_dir_all(&trash_dir));
+    let dest: path::PathBuf = trash_dir.join(trash_file_name(path, suffix));
+    if dest.exists() {
+        return Err(Error::IO(io::Error::new(io::ErrorKind::AlreadyExists,
+                                            format!("{} already exists", dest.display()))));
+    }
+    try!(fs::rename(path, &dest));
+    Ok(())
+}
+
+/// `EnvSet`の変数を他のツール向けの形式で出力する。
+fn dump(dirs: &[path::PathBuf], args: &DumpArgs) -> Result<()> {
+    let mut env_set: EnvSet = try!(EnvSet::new(dirs, &args.env_set));
+    try!(decrypt_values(&mut env_set));
+    try!(env_set.interpolate());
+    print!("{}", try!(args.format.dump(&env_set)));
+    Ok(())
+}
+
+/// `EnvSet`ファイルの変数の値、または変数名が無い場合はファイル全体を暗号化して保存する。
+/// 鍵ファイルが無い場合は作成する。
+fn encrypt(dirs: &[path::PathBuf], args: &EncryptArgs) -> Result<()> {
+    let var_name: &String = match args.key {
+        Some(ref var_name) => var_name,
+        None => return encrypt_file(dirs, &args.env_set),
+    };
+    let mut env_set: EnvSet = try!(EnvSet::load(dirs, &args.env_set));
+    let value: String = match env_set.env(var_name) {
+        Some(value) if secret::is_encrypted(value) => return Ok(()),
+        Some(value) => value.clone(),
+        None => return Err(Error::VariableNotFound(args.env_set.clone(), var_name.clone())),
+    };
+    let key: Key = try!(Key::load_or_create());
+    env_set.set_env(var_name.clone(), try!(key.encrypt(&value, var_name)));
+    env_set.write_to_file()
+}
+
+/// `foo.yaml`を暗号化して`foo.yaml.enc`に保存し、元のファイルを削除する。
+fn encrypt_file(dirs: &[path::PathBuf], env_set_name: &EnvSetName) -> Result<()> {
+    let path: path::PathBuf = try!(EnvSet::find_file_path(dirs, env_set_name));
+    if envset::is_encrypted_file(&path) {
+        return Ok(());
+    }
+    let content: String = try!(EnvSet::read_file(&path));
+    try!(EnvSet::write_file(envset::encrypted_path(&path), &content));
+    try!(fs::remove_file(&path));
+    Ok(())
+}
+
+/// `EnvSet`ファイルの暗号化された値を全て復号して保存する。
+/// ファイル全体が暗号化されている場合は、暗号化されていないファイルに戻す。
+fn decrypt(dirs: &[path::PathBuf], env_set_name: &EnvSetName) -> Result<()> {
+    let path: path::PathBuf = try!(EnvSet::find_file_path(dirs, env_set_name));
+    if envset::is_encrypted_file(&path) {
+        let content: String = try!(EnvSet::read_file(&path));
+        try!(EnvSet::write_file(envset::decrypted_path(&path), &content));
+        try!(fs::remove_file(&path));
+    }
+
+    let mut env_set: EnvSet = try!(EnvSet::load(dirs, env_set_name));
+    if env_set.has_encrypted() {
+        try!(env_set.decrypt(&try!(Key::load())));
+        try!(env_set.write_to_file());
+    }
+    Ok(())
+}
+
+/// 暗号化された値がある場合だけ鍵ファイルを読み込んで復号する。
+fn decrypt_values(env_set: &mut EnvSet) -> Result<()> {
+    if env_set.has_encrypted() {
+        env_set.decrypt(&try!(Key::load()))
+    } else {
+        Ok(())
+    }
+}
+
+/// 現在のシェルで`eval`して環境変数を設定するためのスクリプトを出力する。
+fn export(dirs: &[path::PathBuf], args: &ExportArgs) -> Result<()> {
+    let mut env_set: EnvSet = try!(EnvSet::new(dirs, &args.env_set));
+    try!(decrypt_values(&mut env_set));
+    try!(env_set.interpolate());
+
+    // 途中で失敗した場合に一部だけが`eval`されないよう、全ての行を作ってから出力する。
+    let mut lines: Vec<String> = Vec::new();
+    for k in env_set.unset_vars() {
+        lines.push(try!(args.shell.unset_line(k)));
+    }
+    for (k, v) in env_set.iter() {
+        lines.push(try!(args.shell.export_line(k, v)));
+    }
+    for line in lines {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// 変数の値を`EnvSet`ファイルに書き込む。`EnvSet`が無い場合は新しく作る。
+fn set(dirs: &[path::PathBuf], args: &SetArgs) -> Result<()> {
+    let mut env_set: EnvSet = if try!(EnvSet::find_file_path(dirs, &args.env_set)).is_file() {
+        try!(EnvSet::load(dirs, &args.env_set))
+    } else {
+        try!(EnvSet::empty(dirs, &args.env_set))
+    };
+
+    try!(check_vars(&env_set, &args.vars));
+    for &(ref k, ref v) in &args.vars {
+        env_set.set_env(k.clone(), v.clone());
+    }
+    env_set.write_to_file()
+}
+
+/// `EnvSet`に書き込む変数の名前と値を検査し、使えないものがあればその変数名を全て列挙したエラーを返す。
+fn check_vars(env_set: &EnvSet, vars: &[(String, String)]) -> Result<()> {
+    let invalid_keys: Vec<String> = vars.iter()
+                                        .filter(|&&(ref k, ref v)| {
+                                            envset::check_name(k).is_some() ||
+                                            envset::check_value(v).is_some()
+                                        })
+                                        .map(|&(ref k, _)| k.clone())
+                                        .collect();
+    if invalid_keys.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::InvalidVariables(env_set.file_path().to_path_buf(), invalid_keys))
+    }
+}
+
+/// 変数の指定を`EnvSet`ファイルから削除する。指定されていない変数は無視する。
+fn unset(dirs: &[path::PathBuf], args: &UnsetArgs) -> Result<()> {
+    if !try!(EnvSet::find_file_path(dirs, &args.env_set)).is_file() {
+        return Err(Error::EnvSetNotFound(args.env_set.clone()));
+    }
+    let mut env_set: EnvSet = try!(EnvSet::load(dirs, &args.env_set));
+    let mut changed = false;
+    for k in &args.keys {
+        changed |= env_set.remove_env(k);
+    }
+    if changed {
+        try!(env_set.write_to_file());
+    }
+    Ok(())
+}
+
+/// 継承、暗号化及び`${NAME}`参照を解決した変数の値を表示する。変数が無い場合はエラーとする。
+fn get(dirs: &[path::PathBuf], args: &GetArgs) -> Result<()> {
+    let mut env_set: EnvSet = try!(EnvSet::new(dirs, &args.env_set));
+    try!(decrypt_values(&mut env_set));
+    try!(env_set.interpolate());
+    match env_set.env(&args.key) {
+        Some(value) => {
+            println!("{}", value);
+            Ok(())
+        }
+        None => Err(Error::VariableNotFound(args.env_set.clone(), args.key.clone())),
+    }
+}
+
+/// 継承と`${NAME}`参照を解決した後の`EnvSet`の内容を表示する。
+fn show(dirs: &[path::PathBuf], args: &ShowArgs) -> Result<()> {
+    let mut env_set: EnvSet = try!(EnvSet::new(dirs, &args.env_set));
+    try!(env_set.interpolate());
+
+    for (k, v) in env_set.iter() {
+        if args.mask {
+            println!("{}=********", k);
+        } else {
+            println!("{}={}", k, v);
+        }
+    }
+    for k in env_set.unset_vars() {
+        println!("{} (unset)", k);
+    }
+    Ok(())
+}
+
+/// 重ね合わせた各`EnvSet`とその継承元のスキーマで、重ね合わせた後の`EnvSet`を検査する。
+/// 違反は全てのスキーマについてまとめて報告する。
+fn check_schemas(dirs: &[path::PathBuf], env_set: &EnvSet) -> Result<()> {
+    let mut violations: Vec<(EnvSetName, Vec<(String, String)>)> = Vec::new();
+    for name in env_set.lineage() {
+        if let Some(schema) = try!(Schema::load(dirs, name)) {
+            let schema_violations: Vec<(String, String)> = schema.check(env_set);
+            if !schema_violations.is_empty() {
+                violations.push((name.clone(), schema_violations));
+            }
+        }
+    }
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::SchemaViolations(violations))
+    }
+}
+
+/// 指定された`EnvSet`を左から順に重ね合わせる。後の`EnvSet`の値ほど優先される。
+fn load_env_sets(dirs: &[path::PathBuf],
+                 env_set_names: &[EnvSetName],
+                 verbose: bool)
+                 -> Result<EnvSet> {
+    let mut env_sets: Vec<EnvSet> = Vec::new();
+    for env_set_name in env_set_names {
+        env_sets.push(try!(EnvSet::new(dirs, env_set_name)));
+    }
+    if verbose {
+        report_overrides(&env_sets);
+    }
+
+    let mut env_sets = env_sets.into_iter();
+    let mut merged: EnvSet = try!(env_sets.next().ok_or(Error::NoEnvSetSpecified));
+    for env_set in env_sets {
+        merged.merge(env_set);
+    }
+    Ok(merged)
+}
+
+/// 変数ごとに、値が採用された`EnvSet`と上書きされた`EnvSet`を標準エラー出力に表示する。
+fn report_overrides(env_sets: &[EnvSet]) {
+    let mut providers: BTreeMap<&str, Vec<&EnvSet>> = BTreeMap::new();
+    for env_set in env_sets {
+        for k in env_set.iter().map(|(k, _)| k).chain(env_set.unset_vars()) {
+            providers.entry(k).or_insert_with(Vec::new).push(env_set);
+        }
+    }
+    for (key, env_sets) in providers {
+        if let Some((winner, overridden)) = env_sets.split_last() {
+            let unset = if winner.is_unset(key) { " unset" } else { "" };
+            if overridden.is_empty() {
+                eprintln!("{}: {}{}", key, winner.name(), unset);
+            } else {
+                let names: Vec<&str> = overridden.iter().map(|env_set| &**env_set.name()).collect();
+                eprintln!("{}: {}{} (overrides {})", key, winner.name(), unset, names.join(", "));
+            }
+        }
+    }
+}
+
 fn help() -> Result<()> {
     println!("[Usage]
-  * `envars run ENV_SET_NAME COMMAND`
+  * `envars run [OPTIONS] ENV_SET_NAME[,ENV_SET_NAME...] [--] COMMAND`
+  * `envars run [OPTIONS] -e ENV_SET_NAME [-e ENV_SET_NAME...] [--] COMMAND`
+  * `envars exec [OPTIONS] ENV_SET_NAME[,ENV_SET_NAME...] [--] COMMAND`
+  * `envars export [--shell bash|zsh|fish|powershell|nu] ENV_SET_NAME`
+  * `envars import ENV_SET_NAME [--from FILE]`
+  * `envars lint [--json] [ENV_SET_NAME...]`
   * `envars list`
-  * `envars new ENV_SET_NAME`
+  * `envars dump [--format dotenv|json|systemd|docker-env] ENV_SET_NAME`
+  * `envars new [--format yaml|toml|json] ENV_SET_NAME`
+  * `envars show [--mask] ENV_SET_NAME`
+  * `envars get ENV_SET_NAME VAR`
+  * `envars set ENV_SET_NAME VAR=VALUE...`
+  * `envars unset ENV_SET_NAME VAR...`
   * `envars edit ENV_SET_NAME`
+  * `envars encrypt ENV_SET_NAME [VAR]`
+  * `envars decrypt ENV_SET_NAME`
+  * `envars delete [--yes] [--trash] ENV_SET_NAME...` (or `rm`)
+  * `envars cp [--force] SRC_ENV_SET_NAME DST_ENV_SET_NAME`
+  * `envars mv [--force] SRC_ENV_SET_NAME DST_ENV_SET_NAME`
   * `envars help`
 
+EnvSets are searched in these directories in order, and the first one found is used:
+  1. `--config-dir DIR` given before the mode (repeatable), if any.
+  2. Otherwise, directories listed in `$ENVARS_PATH` (separated like `$PATH`), if set.
+  3. Otherwise, `.envars/` of the current directory or its ancestors, `$XDG_CONFIG_HOME/envars`
+     and `/etc/envars`.
+`list` mode shows the EnvSets in each directory and marks the ones shadowed by earlier directories.
+
+`run` mode merges multiple EnvSets from left to right; later EnvSets win.
+`exec` mode replaces envars with COMMAND (on Unix), so COMMAND keeps the PID of envars
+and receives signals directly. It accepts the same options as `run` mode.
+
+`run` mode options:
+  * `-v`, `--verbose`: Show which EnvSet each variable came from.
+  * `--clean`, `--isolate`: Don't inherit the environment of envars. Only the EnvSet's variables
+    and PATH, HOME (PATH, SYSTEMROOT, USERPROFILE on Windows) are passed to COMMAND.
+  * `--keep VAR[,VAR...]`: Pass VAR to COMMAND as well with `--clean`.
+  Options must come before ENV_SET_NAME. Put `--` before a COMMAND starting with `-`.
+
+`export` mode prints a script setting the variables of the EnvSet for `--shell` (default: bash).
+Use it like `eval \"$(envars export ENV_SET_NAME)\"`.
+Variable names other than `[A-Za-z_][A-Za-z0-9_]*` are rejected.
+`import` mode creates a new EnvSet from a dotenv file (default: `.env`).
+`dump` mode prints the variables of the EnvSet in `--format` (default: dotenv).
+`show` mode prints the variables of the EnvSet after resolving `extends` and `${{NAME}}`.
+`--mask` hides the values.
+`get` mode prints the value of VAR in the EnvSet, and exits with 1 if VAR is not defined.
+`set` mode writes VARs to the EnvSet file, creating the EnvSet if needed.
+`unset` mode removes VARs from the EnvSet file.
+`lint` mode checks EnvSets (default: all EnvSets) and exits with 1 if any problem is found.
+References to variables not defined in the EnvSet are reported as warnings, which don't fail.
+`--json` prints the problems in JSON.
+`new` mode creates `ENV_SET_NAME.yaml` (or `.toml`, `.json` with `--format`)
+in the first EnvSet directory.
 `edit` mode open the EnvSet file with the editor (defined `$EDITOR` or `%EDITOR%`).
+`encrypt` mode encrypts the value of VAR in the EnvSet file with the key file
+(`$ENVARS_KEY_FILE` or `$XDG_DATA_HOME/envars/key`, created if missing).
+Encrypted values are decrypted by `run`, `exec`, `export`, `dump` and `get`.
+Without VAR, the whole file is encrypted into `ENV_SET_NAME.yaml.enc`.
+`edit` mode decrypts it into a private temporary file and encrypts it again after the editor exits.
+`decrypt` mode writes the EnvSet file back in plain text.
+`delete` mode removes EnvSet files after confirmation (skipped with `-y`, `--yes`).
+`--trash` moves them (and their schemas) to `.trash` in the EnvSet's directory instead.
+`cp` and `mv` modes copy and rename an EnvSet (and its schema) in its directory.
+They don't overwrite an existing EnvSet unless `-F`, `--force` is given,
+in which case the new files replace it in its directory.
 ");
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
+    use cmdargs::{CopyArgs, DeleteArgs, GetArgs, SetArgs, UnsetArgs};
+    use envset::{EnvSet, EnvSetName};
+    use error::Error;
+    use std::env;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::path::{Path, PathBuf};
+    use std::process;
+
+    fn write_file(path: &Path, content: &str) {
+        fs::File::create(path).unwrap().write_all(content.as_bytes()).unwrap();
+    }
+
+    fn read_file(path: &Path) -> String {
+        let mut content = String::new();
+        fs::File::open(path).unwrap().read_to_string(&mut content).unwrap();
+        content
+    }
+
+    fn copy_args(src: &str, dst: &str, force: bool) -> CopyArgs {
+        CopyArgs {
+            src: EnvSetName::new(src).unwrap(),
+            dst: EnvSetName::new(dst).unwrap(),
+            force: force,
+        }
+    }
+
     #[test]
     fn test_anything() {}
+
+    #[test]
+    fn test_set_get_unset() {
+        let dir: PathBuf = env::temp_dir().join(format!("envars-set-get-unset-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dirs = [dir.clone()];
+        let name = EnvSetName::new("app").unwrap();
+        let get_args = |key: &str| {
+            GetArgs {
+                env_set: name.clone(),
+                key: key.to_owned(),
+            }
+        };
+
+        let set_args = SetArgs {
+            env_set: name.clone(),
+            vars: vec![("HOST".to_owned(), "localhost".to_owned()),
+                       ("URL".to_owned(), "http://${HOST}/ it's \"quoted\"".to_owned()),
+                       ("TOKEN".to_owned(), "s3cret".to_owned())],
+        };
+        super::set(&dirs, &set_args).unwrap();
+        let env_set = EnvSet::load(&dirs, &name).unwrap();
+        assert_eq!(env_set.env("URL"), Some(&"http://${HOST}/ it's \"quoted\"".to_owned()));
+        assert!(super::get(&dirs, &get_args("URL")).is_ok());
+        let reserved_args = SetArgs {
+            env_set: name.clone(),
+            vars: vec![("extends".to_owned(), "base".to_owned())],
+        };
+        match super::set(&dirs, &reserved_args) {
+            Err(Error::InvalidVariables(_, ref keys)) => assert_eq!(keys, &["extends"]),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let unset_args = UnsetArgs {
+            env_set: name.clone(),
+            keys: vec!["TOKEN".to_owned(), "MISSING".to_owned()],
+        };
+        super::unset(&dirs, &unset_args).unwrap();
+        let result = super::get(&dirs, &get_args("TOKEN"));
+        let env_set = EnvSet::load(&dirs, &name).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        match result {
+            Err(Error::VariableNotFound(ref env_set, ref key)) => {
+                assert_eq!((&**env_set, &**key), ("app", "TOKEN"))
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(env_set.env("TOKEN"), None);
+        assert!(!env_set.is_unset("TOKEN"));
+        assert_eq!(env_set.env("HOST"), Some(&"localhost".to_owned()));
+    }
+
+    #[test]
+    fn test_delete_to_trash() {
+        let dir: PathBuf = env::temp_dir()
+                               .join(format!("envars-delete-to-trash-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dirs = [dir.clone()];
+        let args = DeleteArgs {
+            env_sets: vec![EnvSetName::new("app").unwrap(), EnvSetName::new("app").unwrap()],
+            yes: true,
+            trash: true,
+        };
+        for content in &["A: first\n", ": broken\n"] {
+            write_file(&dir.join("app.yaml"), content);
+            write_file(&dir.join("app.schema.yaml"), content);
+            super::delete(&dirs, &args).unwrap();
+        }
+        let mut trashed: Vec<String> = Vec::new();
+        for entry in fs::read_dir(dir.join(".trash")).unwrap() {
+            trashed.push(read_file(&entry.unwrap().path()));
+        }
+        let remains = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+
+        trashed.sort();
+        assert_eq!(trashed, vec![": broken\n", ": broken\n", "A: first\n", "A: first\n"]);
+        assert_eq!(remains, 1);
+    }
+
+    #[test]
+    fn test_copy_and_move() {
+        let root: PathBuf = env::temp_dir().join(format!("envars-copy-and-move-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let dirs = [root.join("high"), root.join("low")];
+        fs::create_dir_all(&dirs[0]).unwrap();
+        fs::create_dir_all(&dirs[1]).unwrap();
+        write_file(&dirs[1].join("base.yaml"), "A: base\n");
+        write_file(&dirs[1].join("base.schema.yaml"), "A: string\n");
+        write_file(&dirs[1].join("plain.json"), "{\"A\": \"plain\"}");
+        write_file(&dirs[1].join("orphan.schema.yaml"), "A: int\n");
+        write_file(&dirs[0].join("other.toml"), "A = \"other\"\n");
+        write_file(&dirs[0].join("other.schema.yaml"), "A: int\n");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let permissions = fs::Permissions::from_mode(0o600);
+            fs::set_permissions(dirs[1].join("base.yaml"), permissions).unwrap();
+        }
+
+        super::copy(&dirs, &copy_args("base", "app", false), false).unwrap();
+        let exists_result = super::copy(&dirs, &copy_args("base", "app", false), false);
+        super::copy(&dirs, &copy_args("plain", "orphan", false), false).unwrap();
+        super::copy(&dirs, &copy_args("plain", "other", true), true).unwrap();
+
+        let mut files: Vec<String> = Vec::new();
+        for dir in &dirs {
+            for entry in fs::read_dir(dir).unwrap() {
+                let file_name = entry.unwrap().file_name();
+                files.push(format!("{}/{}",
+                                   dir.file_name().unwrap().to_string_lossy(),
+                                   file_name.to_string_lossy()));
+            }
+        }
+        files.sort();
+        #[cfg(unix)]
+        let mode: u32 = {
+            use std::os::unix::fs::PermissionsExt;
+            fs::metadata(dirs[1].join("app.yaml")).unwrap().permissions().mode()
+        };
+        let app = read_file(&dirs[1].join("app.yaml"));
+        let app_schema = read_file(&dirs[1].join("app.schema.yaml"));
+        let other = read_file(&dirs[0].join("other.json"));
+        fs::remove_dir_all(&root).unwrap();
+
+        match exists_result {
+            Err(Error::EnvSetExists(ref name)) => assert_eq!(&**name, "app"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(files,
+                   vec!["high/other.json",
+                        "low/app.schema.yaml",
+                        "low/app.yaml",
+                        "low/base.schema.yaml",
+                        "low/base.yaml",
+                        "low/orphan.json",
+                        "low/orphan.schema.yaml"]);
+        assert_eq!(app, "A: base\n");
+        assert_eq!(app_schema, "A: string\n");
+        assert_eq!(other, "{\"A\": \"plain\"}");
+        #[cfg(unix)]
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_copy_rollback() {
+        let dir: PathBuf = env::temp_dir().join(format!("envars-copy-rollback-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dirs = [dir.clone()];
+        write_file(&dir.join("base.yaml"), "A: base\n");
+        write_file(&dir.join("base.schema.yaml"), "A: string\n");
+        write_file(&dir.join("app.yaml"), "A: app\n");
+        // スキーマを用意する場所を塞いで、途中で失敗させる。
+        fs::create_dir(dir.join(format!(".app.schema.yaml.{}.tmp", process::id()))).unwrap();
+
+        let result = super::copy(&dirs, &copy_args("base", "app", true), true);
+        let mut files: Vec<String> = fs::read_dir(&dir)
+                                         .unwrap()
+                                         .map(|entry| entry.unwrap().file_name())
+                                         .map(|name| name.to_string_lossy().into_owned())
+                                         .filter(|name| !name.starts_with('.'))
+                                         .collect();
+        files.sort();
+        let staged_env_set = dir.join(format!(".app.yaml.{}.tmp", process::id())).exists();
+        let app = read_file(&dir.join("app.yaml"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_err());
+        assert_eq!(files, vec!["app.yaml", "base.schema.yaml", "base.yaml"]);
+        assert!(!staged_env_set);
+        assert_eq!(app, "A: app\n");
+    }
+
+    #[test]
+    fn test_list_shadowed() {
+        let root: PathBuf = env::temp_dir().join(format!("envars-list-shadowed-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let dirs = [root.join("high"), root.join("missing"), root.join("low")];
+        fs::create_dir_all(&dirs[0]).unwrap();
+        fs::create_dir_all(&dirs[2]).unwrap();
+        write_file(&dirs[0].join("app.yaml"), "");
+        write_file(&dirs[2].join("app.toml"), "");
+        write_file(&dirs[2].join("base.json"), "{}");
+
+        let lines = super::list_lines(&dirs).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(lines,
+                   vec![format!("{}:", dirs[0].display()),
+                        "  app.yaml".to_owned(),
+                        format!("{}:", dirs[2].display()),
+                        format!("  app.toml (shadowed by {})", dirs[0].join("app.yaml").display()),
+                        "  base.json".to_owned()]);
+    }
 }
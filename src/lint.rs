@@ -0,0 +1,262 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 Siphilia
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! `EnvSet`ファイルの問題点の検査。
+
+use envset;
+use envset::{EXTENDS_KEY, EnvSet, EnvSetName};
+use error::Error;
+use schema::Schema;
+use secret;
+use secret::Key;
+use serde_json::Map;
+use serde_json::Value as Json;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use storage;
+use storage::{Table, Value};
+
+/// 問題の重大度。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Severity {
+    Error,
+    /// 使う環境によっては問題とならないもの。警告だけの場合は検査に失敗しない。
+    Warning,
+}
+
+/// 検査で見つかった一つの問題。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Problem {
+    pub env_set: EnvSetName,
+    pub path: PathBuf,
+    /// 問題のある変数名。ファイル全体の問題の場合は`None`。
+    pub key: Option<String>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn to_json(&self) -> Json {
+        let mut object = Map::new();
+        object.insert("env_set".to_owned(), Json::from(self.env_set.to_string()));
+        object.insert("path".to_owned(), Json::from(self.path.to_string_lossy().into_owned()));
+        object.insert("key".to_owned(), self.key.clone().map_or(Json::Null, Json::from));
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        object.insert("severity".to_owned(), Json::from(severity));
+        object.insert("message".to_owned(), Json::from(self.message.clone()));
+        Json::Object(object)
+    }
+}
+
+/// `EnvSet`ファイルを検査し、見つかった問題を全て返す。
+///
+/// 読み込みや継承、`${NAME}`参照の解決に失敗した場合も問題として返す。
+/// `EnvSet`で定義されていない変数の参照は、使う環境で定義されていれば問題ないので警告とする。
+pub fn lint(dirs: &[PathBuf], env_set_name: &EnvSetName) -> Vec<Problem> {
+    let mut linter = Linter {
+        dirs: dirs,
+        env_set: env_set_name.clone(),
+        path: PathBuf::new(),
+        problems: Vec::new(),
+    };
+    linter.lint();
+    linter.problems
+}
+
+struct Linter<'a> {
+    /// `EnvSet`ファイルを探すディレクトリ。
+    dirs: &'a [PathBuf],
+    env_set: EnvSetName,
+    path: PathBuf,
+    problems: Vec<Problem>,
+}
+
+impl<'a> Linter<'a> {
+    fn lint(&mut self) {
+        self.path = match EnvSet::find_file_path(self.dirs, &self.env_set) {
+            Ok(path) => path,
+            Err(error) => return self.report(None, error.to_string()),
+        };
+        // 鍵ファイルが無い環境では、ファイル全体が暗号化された`EnvSet`は検査できない。
+        if envset::is_encrypted_file(&self.path) {
+            if let Err(error) = Key::load() {
+                return self.report(None, format!("cannot check: key not available ({})", error));
+            }
+        }
+        let content: String = match EnvSet::read_file(&self.path) {
+            Ok(content) => content,
+            Err(error) => return self.report(None, error.to_string()),
+        };
+        let storage = storage::from_path(envset::decrypted_path(&self.path))
+                          .unwrap_or(&storage::YamlStorage);
+        let table: Table = match storage.load(&content) {
+            Ok(table) => table,
+            Err(_) => {
+                // 位置付きのエラーメッセージは`EnvSet`の読み込みで作られる。
+                if let Err(error) = EnvSet::load(self.dirs, &self.env_set) {
+                    self.report(None, error.to_string());
+                }
+                return;
+            }
+        };
+
+        for key in storage.duplicate_keys(&content) {
+            self.report(Some(&key), "duplicate variable name".to_owned());
+        }
+        // 変数名や値が不正な場合は`EnvSet`として読み込めないので、以降の検査は行わない。
+        let mut is_loadable = true;
+        for (key, value) in table.iter().filter(|&(k, _)| k != EXTENDS_KEY) {
+            if let Some(reason) = envset::check_name(key) {
+                is_loadable = false;
+                self.report(Some(key), reason.to_owned());
+            }
+            match *value {
+                Value::String(ref s) => {
+                    if let Some(reason) = envset::check_value(s) {
+                        is_loadable = false;
+                        self.report(Some(key), reason.to_owned());
+                    }
+                    self.check_value(key, s);
+                }
+                Value::Array(_) | Value::Table(_) => {
+                    is_loadable = false;
+                    self.report(Some(key), "a sequence or mapping can't be a value".to_owned());
+                }
+                Value::Integer(_) | Value::Float(_) | Value::Boolean(_) | Value::Null => {}
+            }
+        }
+
+        if is_loadable {
+            self.check_resolved();
+        }
+    }
+
+    /// 継承と`${NAME}`参照を解決し、`EnvSet`と継承元にスキーマがあればそれに従っているか検査する。
+    /// 結果がCIのログなどに残っても値が漏れないよう、暗号化された値は復号せず、スキーマでも検査しない。
+    fn check_resolved(&mut self) {
+        let mut env_set: EnvSet = match EnvSet::new(self.dirs, &self.env_set) {
+            Ok(env_set) => env_set,
+            Err(error) => return self.report(None, error.to_string()),
+        };
+        let encrypted: Vec<String> = env_set.iter()
+                                            .filter(|&(_, v)| secret::is_encrypted(v))
+                                            .map(|(k, _)| k.clone())
+                                            .collect();
+        // 検査する環境によって結果が変わらないよう、親プロセスの環境変数は用いない。
+        // 自身を参照する変数は親プロセスの値を前提としているので、空文字列とみなす。
+        let defined: BTreeSet<String> = env_set.iter().map(|(k, _)| k.clone()).collect();
+        let mut resolved: EnvSet = env_set.clone();
+        let result = match resolved.interpolate_with(|name| {
+            if defined.contains(name) {
+                Some(String::new())
+            } else {
+                None
+            }
+        }) {
+            Err(error @ Error::UndefinedVariables(_)) => {
+                self.warn(None, error.to_string());
+                // 定義されていない変数も空文字列とみなして、スキーマの検査を続ける。
+                resolved = env_set;
+                resolved.interpolate_with(|_| Some(String::new()))
+            }
+            result => result,
+        };
+        if let Err(error) = result {
+            return self.report(None, error.to_string());
+        }
+        for name in resolved.lineage() {
+            match Schema::load(self.dirs, name) {
+                Ok(Some(schema)) => {
+                    for (key, message) in schema.check(&resolved) {
+                        if !encrypted.contains(&key) {
+                            self.report(Some(&key), message);
+                        }
+                    }
+                }
+                Ok(None) => {}
+                Err(error) => self.report(None, error.to_string()),
+            }
+        }
+    }
+
+    fn check_value(&mut self, key: &str, value: &str) {
+        if value.is_empty() {
+            self.report(Some(key), "empty value".to_owned());
+        } else if value.ends_with(char::is_whitespace) {
+            self.report(Some(key), "trailing whitespace in value".to_owned());
+        }
+    }
+
+    fn report(&mut self, key: Option<&str>, message: String) {
+        self.push(key, Severity::Error, message);
+    }
+
+    fn warn(&mut self, key: Option<&str>, message: String) {
+        self.push(key, Severity::Warning, message);
+    }
+
+    fn push(&mut self, key: Option<&str>, severity: Severity, message: String) {
+        self.problems.push(Problem {
+            env_set: self.env_set.clone(),
+            path: self.path.clone(),
+            key: key.map(|k| k.to_owned()),
+            severity: severity,
+            message: message,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Severity, lint};
+    use envset::EnvSetName;
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::process;
+
+    #[test]
+    fn test_lint_references() {
+        let dir: PathBuf = env::temp_dir()
+                               .join(format!("envars-lint-references-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::File::create(dir.join("app.yaml"))
+            .unwrap()
+            .write_all(b"PATH: /opt/bin:${PATH}\nURL: http://${ENVARS_LINT_TEST_HOST}/\n")
+            .unwrap();
+        fs::File::create(dir.join("app.schema.yaml")).unwrap().write_all(b"URL: int\n").unwrap();
+        // 親プロセスで定義されていても、`EnvSet`で定義されていない参照は警告とする。
+        env::set_var("ENVARS_LINT_TEST_HOST", "localhost");
+        let problems = lint(&[dir.clone()], &EnvSetName::new("app").unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(problems.len(), 2);
+        assert_eq!(problems[0].severity, Severity::Warning);
+        assert!(problems[0].message.contains("ENVARS_LINT_TEST_HOST"));
+        assert_eq!(problems[1].severity, Severity::Error);
+        assert_eq!(problems[1].key, Some("URL".to_owned()));
+    }
+}
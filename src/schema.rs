@@ -0,0 +1,330 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 Siphilia
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! `EnvSet`の変数を検査するスキーマ。
+//!
+//! スキーマは`EnvSet`ファイルと同じディレクトリの`ENV_SET_NAME.schema.yaml`に、
+//! 変数名から規則への対応表として書く。
+//!
+//! ```yaml
+//! DATABASE_URL:
+//!   required: true
+//!   type: url
+//! LOG_LEVEL:
+//!   type: enum
+//!   values: [debug, info, warn]
+//! RELEASE:
+//!   pattern: "^v[0-9]+$"
+//! PORT: int
+//! ```
+//!
+//! 規則の代わりに型名だけを書くこともできる。
+
+use envset::{EnvSet, EnvSetName};
+use error::{Error, Result, SyntaxError};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::result;
+use storage::{LoadError, Storage, Table, Value, YamlStorage};
+
+/// 変数の値の型。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum VarType {
+    String,
+    Int,
+    /// `true`, `false`, `1`, `0`, `yes`, `no`のいずれか。大文字と小文字は区別しない。
+    Bool,
+    /// `scheme://...`の形式。
+    Url,
+    /// 存在するファイルまたはディレクトリのパス。
+    Path,
+    Enum(Vec<String>),
+}
+
+/// 一つの変数に対する規則。
+#[derive(Clone, Debug)]
+pub struct VarRule {
+    pub required: bool,
+    pub var_type: VarType,
+    pub pattern: Option<Regex>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Schema {
+    path: PathBuf,
+    rules: BTreeMap<String, VarRule>,
+}
+
+impl Schema {
+    /// `EnvSet`のスキーマを読み込む。スキーマファイルが無い場合は`None`を返す。
+    pub fn load(dirs: &[PathBuf], env_set_name: &EnvSetName) -> Result<Option<Schema>> {
+        let path: PathBuf = try!(Self::file_path(dirs, env_set_name));
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let mut content = String::new();
+        try!(fs::File::open(&path).and_then(|mut f| f.read_to_string(&mut content)));
+        let table: Table = try!(YamlStorage.load(&content).map_err(|e| {
+            match e {
+                LoadError::Syntax(line, col, message) => {
+                    Error::Syntax(SyntaxError::new(env_set_name,
+                                                   &path,
+                                                   &content,
+                                                   line,
+                                                   col,
+                                                   message))
+                }
+                LoadError::NotMapping => {
+                    Error::InvalidSchema(path.clone(), "not a mapping".to_owned())
+                }
+                LoadError::Unsupported(message) => Error::InvalidSchema(path.clone(), message),
+            }
+        }));
+
+        let mut rules = BTreeMap::new();
+        for (key, value) in &table {
+            let rule: VarRule = try!(make_rule(value).map_err(|message| {
+                Error::InvalidSchema(path.clone(), format!("{}: {}", key, message))
+            }));
+            rules.insert(key.clone(), rule);
+        }
+        Ok(Some(Schema {
+            path: path,
+            rules: rules,
+        }))
+    }
+
+    /// スキーマファイルは`EnvSet`ファイルと同じディレクトリに置く。
+    pub fn file_path<P: AsRef<Path>>(dirs: &[PathBuf], env_set_name: P) -> Result<PathBuf> {
+        let env_set_path: PathBuf = try!(EnvSet::find_file_path(dirs, &env_set_name));
+        Ok(Self::file_path_in(env_set_path.parent().unwrap_or(Path::new("")), env_set_name))
+    }
+
+    pub fn file_path_in<D: AsRef<Path>, P: AsRef<Path>>(dir: D, env_set_name: P) -> PathBuf {
+        let mut file_name = env_set_name.as_ref().as_os_str().to_os_string();
+        file_name.push(".schema.yaml");
+        dir.as_ref().join(file_name)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 規則に反する変数を全て調べ、変数名と説明の組を返す。
+    /// `EnvSet`は継承と`${NAME}`参照を解決した後のものを与える。
+    /// 復号した値を含む変数については、説明に値を含めない。
+    pub fn check(&self, env_set: &EnvSet) -> Vec<(String, String)> {
+        let mut violations = Vec::new();
+        for (key, rule) in &self.rules {
+            match env_set.env(key) {
+                Some(value) => {
+                    if let Some(message) = rule.check(value, env_set.is_secret(key)) {
+                        violations.push((key.clone(), message));
+                    }
+                }
+                None if rule.required => {
+                    violations.push((key.clone(), "required but not set".to_owned()))
+                }
+                None => {}
+            }
+        }
+        violations
+    }
+}
+
+impl VarRule {
+    fn check(&self, value: &str, is_secret: bool) -> Option<String> {
+        let shown: String = if is_secret {
+            "a secret value".to_owned()
+        } else {
+            format!("{:?}", value)
+        };
+        if !self.var_type.accepts(value) {
+            return Some(format!("expected {}, got {}", self.var_type, shown));
+        }
+        match self.pattern {
+            Some(ref pattern) if !pattern.is_match(value) => {
+                Some(format!("{} doesn't match /{}/", shown, pattern.as_str()))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl VarType {
+    fn from_name(name: &str, values: Option<Vec<String>>) -> result::Result<VarType, String> {
+        match (name, values) {
+            ("string", None) => Ok(VarType::String),
+            ("int", None) => Ok(VarType::Int),
+            ("bool", None) => Ok(VarType::Bool),
+            ("url", None) => Ok(VarType::Url),
+            ("path", None) => Ok(VarType::Path),
+            ("enum", Some(values)) => Ok(VarType::Enum(values)),
+            ("enum", None) => Err("enum requires values".to_owned()),
+            (_, Some(_)) => Err("values is only for enum".to_owned()),
+            (name, None) => Err(format!("unknown type {}", name)),
+        }
+    }
+
+    fn accepts(&self, value: &str) -> bool {
+        match *self {
+            VarType::String => true,
+            VarType::Int => value.parse::<i64>().is_ok(),
+            VarType::Bool => {
+                ["true", "false", "1", "0", "yes", "no"]
+                    .iter()
+                    .any(|b| b.eq_ignore_ascii_case(value))
+            }
+            VarType::Url => is_url(value),
+            VarType::Path => !value.is_empty() && Path::new(value).exists(),
+            VarType::Enum(ref values) => values.iter().any(|v| v == value),
+        }
+    }
+}
+
+impl fmt::Display for VarType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VarType::String => write!(f, "string"),
+            VarType::Int => write!(f, "int"),
+            VarType::Bool => write!(f, "bool"),
+            VarType::Url => write!(f, "url"),
+            VarType::Path => write!(f, "an existing path"),
+            VarType::Enum(ref values) => write!(f, "one of {}", values.join(", ")),
+        }
+    }
+}
+
+fn make_rule(value: &Value) -> result::Result<VarRule, String> {
+    let table: Table = match *value {
+        Value::String(ref name) => {
+            let mut table = Table::new();
+            table.insert("type".to_owned(), Value::String(name.clone()));
+            table
+        }
+        Value::Table(ref table) => table.clone(),
+        _ => return Err("a rule must be a type name or a mapping".to_owned()),
+    };
+
+    let required = match table.get("required") {
+        Some(&Value::Boolean(b)) => b,
+        Some(_) => return Err("required must be a boolean".to_owned()),
+        None => false,
+    };
+    let values: Option<Vec<String>> = match table.get("values") {
+        Some(&Value::Array(ref values)) => {
+            Some(try!(values.iter().map(scalar_to_string).collect()))
+        }
+        Some(_) => return Err("values must be a sequence".to_owned()),
+        None => None,
+    };
+    let var_type = match table.get("type") {
+        Some(&Value::String(ref name)) => try!(VarType::from_name(name, values)),
+        Some(_) => return Err("type must be a string".to_owned()),
+        None if values.is_some() => return Err("values is only for enum".to_owned()),
+        None => VarType::String,
+    };
+    let pattern = match table.get("pattern") {
+        Some(&Value::String(ref pattern)) => {
+            Some(try!(Regex::new(pattern).map_err(|e| e.to_string())))
+        }
+        Some(_) => return Err("pattern must be a string".to_owned()),
+        None => None,
+    };
+    let rule_keys = ["required", "type", "values", "pattern"];
+    if let Some(key) = table.keys().find(|k| !rule_keys.contains(&k.as_str())) {
+        return Err(format!("unknown key {}", key));
+    }
+
+    Ok(VarRule {
+        required: required,
+        var_type: var_type,
+        pattern: pattern,
+    })
+}
+
+/// `enum`の候補は`EnvSet`の値と同様に数値と真偽値も文字列として扱う。
+fn scalar_to_string(value: &Value) -> result::Result<String, String> {
+    match *value {
+        Value::String(ref s) | Value::Float(ref s) => Ok(s.clone()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        _ => Err("values must be strings, numbers or booleans".to_owned()),
+    }
+}
+
+/// スキームが英字で始まり、英数字と`+`, `-`, `.`から成り、`://`の後が空でない場合にURLとみなす。
+fn is_url(value: &str) -> bool {
+    match value.find("://") {
+        Some(i) => {
+            let (scheme, rest) = (&value[..i], &value[i + 3..]);
+            scheme.chars().next().map_or(false, |c| c.is_ascii_alphabetic()) &&
+            scheme.chars().all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.') &&
+            !rest.is_empty()
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{VarType, is_url, make_rule};
+    use storage::{Storage, Value, YamlStorage};
+
+    #[test]
+    fn test_var_type() {
+        assert!(VarType::Int.accepts("-42"));
+        assert!(!VarType::Int.accepts("4.2"));
+        assert!(VarType::Bool.accepts("Yes"));
+        assert!(!VarType::Bool.accepts("on"));
+        assert!(is_url("postgres://localhost/app"));
+        assert!(!is_url("localhost:5432"));
+        assert!(!is_url("1http://localhost"));
+    }
+
+    #[test]
+    fn test_make_rule() {
+        let table = YamlStorage.load("A: int\n\
+                                      B: {required: true, type: enum, values: [debug, 1]}\n\
+                                      C: {pattern: '^v[0-9]+$'}\n\
+                                      D: {type: enum}\n")
+                               .unwrap();
+        let a = make_rule(&table["A"]).unwrap();
+        assert_eq!(a.var_type, VarType::Int);
+        assert!(!a.required);
+        let b = make_rule(&table["B"]).unwrap();
+        assert!(b.required);
+        assert!(b.check("1", false).is_none());
+        assert!(b.check("info", false).is_some());
+        let c = make_rule(&table["C"]).unwrap();
+        assert!(c.check("v12", false).is_none());
+        assert_eq!(c.check("12", false).unwrap(), "\"12\" doesn't match /^v[0-9]+$/");
+        assert_eq!(c.check("12", true).unwrap(), "a secret value doesn't match /^v[0-9]+$/");
+        assert!(make_rule(&table["D"]).is_err());
+        assert!(make_rule(&Value::Integer(1)).is_err());
+    }
+}
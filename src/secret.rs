@@ -0,0 +1,217 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 Siphilia
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! `EnvSet`の値の暗号化。
+//!
+//! 暗号化された値は`enc:`に続けて、ノンス、暗号文及び認証タグをこの順に連結したものをBase64で表す。
+//! 暗号はAES-256-GCMで、鍵はローカルの鍵ファイルに保存する。
+//! 変数の値は変数名を関連データとして暗号化するので、別の変数に書き写した値は復号できない。
+
+use aes_gcm::{Aes256Gcm, Nonce};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::aead::rand_core::RngCore;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use config;
+use error::{Error, Result};
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// 暗号化された値の接頭辞。
+pub const PREFIX: &'static str = "enc:";
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(PREFIX)
+}
+
+/// 値の暗号化に用いる鍵。
+pub struct Key([u8; KEY_LEN]);
+
+impl Key {
+    /// 鍵ファイルから鍵を読み込む。
+    pub fn load() -> Result<Key> {
+        let path: PathBuf = try!(config::key_file_path());
+        if !path.is_file() {
+            return Err(Error::KeyFileNotFound(path));
+        }
+        let mut content = String::new();
+        try!(fs::File::open(&path).and_then(|mut f| f.read_to_string(&mut content)));
+        match STANDARD.decode(content.trim()) {
+            Ok(ref bytes) if bytes.len() == KEY_LEN => {
+                let mut key = [0; KEY_LEN];
+                key.copy_from_slice(bytes);
+                Ok(Key(key))
+            }
+            _ => Err(Error::InvalidKeyFile(path)),
+        }
+    }
+
+    /// 鍵ファイルから鍵を読み込む。鍵ファイルが無い場合は新しい鍵を作って保存する。
+    pub fn load_or_create() -> Result<Key> {
+        let path: PathBuf = try!(config::key_file_path());
+        if path.exists() {
+            return Self::load();
+        }
+
+        let key: [u8; KEY_LEN] = Aes256Gcm::generate_key(&mut OsRng).into();
+        if let Some(dir) = path.parent() {
+            try!(fs::create_dir_all(dir));
+        }
+        let mut file: fs::File = try!(create_private_file(&path));
+        try!(file.write_all(format!("{}\n", STANDARD.encode(key)).as_bytes()));
+        try!(file.sync_data());
+        Ok(Key(key))
+    }
+
+    /// `aad`は関連データで、復号する時にも同じものを与える必要がある。
+    /// 変数の値では変数名を、ファイル全体では空文字列を用いる。
+    pub fn encrypt(&self, plain: &str, aad: &str) -> Result<String> {
+        let nonce: Nonce<_> = Aes256Gcm::generate_nonce(&mut OsRng);
+
+        let payload = Payload {
+            msg: plain.as_bytes(),
+            aad: aad.as_bytes(),
+        };
+        // 暗号文の後に認証タグが続く。
+        let encrypted: Vec<u8> = try!(self.cipher()
+                                          .encrypt(&nonce, payload)
+                                          .map_err(|_| Error::EncryptFailed));
+
+        let mut bytes: Vec<u8> = nonce.to_vec();
+        bytes.extend_from_slice(&encrypted);
+        Ok(format!("{}{}", PREFIX, STANDARD.encode(bytes)))
+    }
+
+    /// 暗号化された値を復号する。鍵や`aad`が異なる場合や、値が壊れている場合は`None`を返す。
+    pub fn decrypt(&self, value: &str, aad: &str) -> Option<String> {
+        if !is_encrypted(value) {
+            return None;
+        }
+        let bytes: Vec<u8> = match STANDARD.decode(&value[PREFIX.len()..]) {
+            Ok(bytes) if bytes.len() >= NONCE_LEN + TAG_LEN => bytes,
+            _ => return None,
+        };
+        let (nonce, encrypted) = bytes.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: encrypted,
+            aad: aad.as_bytes(),
+        };
+        self.cipher()
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .ok()
+            .and_then(|plain| String::from_utf8(plain).ok())
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(&self.0.into())
+    }
+}
+
+/// 復号した内容を編集するための一時ファイル。
+///
+/// 所有者だけが読み書きできる一時ディレクトリに作られ、エディタのスワップファイルなども含めて
+/// ドロップ時にディレクトリごと削除される。
+pub struct PrivateTempFile {
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl PrivateTempFile {
+    /// `file_name`という名前で`content`を書き込んだ一時ファイルを作る。
+    pub fn create(file_name: &OsStr, content: &str) -> Result<PrivateTempFile> {
+        let dir: PathBuf = env::temp_dir().join(format!("envars-{:016x}", OsRng.next_u64()));
+        try!(create_private_dir(&dir));
+        let temp = PrivateTempFile {
+            path: dir.join(file_name),
+            dir: dir,
+        };
+        let mut file: fs::File = try!(create_private_file(&temp.path));
+        try!(file.write_all(content.as_bytes()));
+        try!(file.sync_data());
+        Ok(temp)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn read(&self) -> Result<String> {
+        let mut content = String::new();
+        try!(fs::File::open(&self.path).and_then(|mut f| f.read_to_string(&mut content)));
+        Ok(content)
+    }
+}
+
+impl Drop for PrivateTempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// 所有者だけが読み書きできるディレクトリを作る。
+#[cfg(unix)]
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    fs::DirBuilder::new().mode(0o700).create(path)
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    fs::DirBuilder::new().create(path)
+}
+
+/// 所有者だけが読み書きできるファイルを作る。
+#[cfg(unix)]
+fn create_private_file(path: &Path) -> io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private_file(path: &Path) -> io::Result<fs::File> {
+    fs::OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KEY_LEN, Key, is_encrypted};
+
+    #[test]
+    fn test_encrypt_and_decrypt() {
+        let key = Key([7; KEY_LEN]);
+        let encrypted = key.encrypt("s3cret", "PASSWORD").unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.contains("s3cret"));
+        assert_eq!(key.decrypt(&encrypted, "PASSWORD"), Some("s3cret".to_owned()));
+        assert_eq!(key.decrypt(&encrypted, "OTHER_PASSWORD"), None);
+        assert_eq!(Key([8; KEY_LEN]).decrypt(&encrypted, "PASSWORD"), None);
+        assert_eq!(key.decrypt("enc:broken", "PASSWORD"), None);
+    }
+}
@@ -0,0 +1,128 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 Siphilia
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use envset;
+use error::Result;
+
+/// `export`モードの出力先のシェル。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    PowerShell,
+    Nu,
+}
+
+impl Shell {
+    pub fn from_name(name: &str) -> Option<Shell> {
+        match name {
+            "bash" | "sh" => Some(Shell::Bash),
+            "zsh" => Some(Shell::Zsh),
+            "fish" => Some(Shell::Fish),
+            "powershell" | "pwsh" => Some(Shell::PowerShell),
+            "nu" | "nushell" => Some(Shell::Nu),
+            _ => None,
+        }
+    }
+
+    /// 現在のシェルで環境変数を設定する一行を返す。値は引用符で囲まれ、展開されない。
+    /// 変数名は引用できないので、シェルの変数名として使えない場合はエラーとする。
+    pub fn export_line(&self, key: &str, value: &str) -> Result<String> {
+        try!(envset::check_portable_name(key));
+        Ok(match *self {
+            Shell::Bash | Shell::Zsh => format!("export {}={}", key, posix_quote(value)),
+            Shell::Fish => format!("set -gx {} {}", key, fish_quote(value)),
+            Shell::PowerShell => format!("$env:{} = {}", key, powershell_quote(value)),
+            Shell::Nu => format!("$env.{} = {}", key, nu_quote(value)),
+        })
+    }
+
+    /// 現在のシェルから環境変数を取り除く一行を返す。
+    pub fn unset_line(&self, key: &str) -> Result<String> {
+        try!(envset::check_portable_name(key));
+        Ok(match *self {
+            Shell::Bash | Shell::Zsh => format!("unset {}", key),
+            Shell::Fish => format!("set -e {}", key),
+            Shell::PowerShell => format!("Remove-Item Env:{} -ErrorAction SilentlyContinue", key),
+            Shell::Nu => format!("hide-env -i {}", key),
+        })
+    }
+}
+
+/// シングルクォートの中では何も展開されないので、`'`だけを`'\''`として閉じ直す。
+fn posix_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// fishのシングルクォートの中では`\\`と`\'`だけがエスケープとして扱われる。
+fn fish_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+/// PowerShellのシングルクォートの中では`''`が`'`となる。
+fn powershell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// nuのraw文字列`r#'...'#`を、値に含まれない数の`#`で囲む。
+fn nu_quote(value: &str) -> String {
+    let mut hashes = String::from("#");
+    while value.contains(&format!("'{}", hashes)) {
+        hashes.push('#');
+    }
+    format!("r{}'{}'{}", hashes, value, hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Shell;
+    use error::Error;
+
+    const VALUE: &'static str = "it's $HOME\\n\n'#";
+
+    #[test]
+    fn test_export_line() {
+        assert_eq!(Shell::Bash.export_line("K", VALUE).unwrap(),
+                   "export K='it'\\''s $HOME\\n\n'\\''#'");
+        assert_eq!(Shell::Fish.export_line("K", VALUE).unwrap(),
+                   "set -gx K 'it\\'s $HOME\\\\n\n\\'#'");
+        assert_eq!(Shell::PowerShell.export_line("K", VALUE).unwrap(),
+                   "$env:K = 'it''s $HOME\\n\n''#'");
+        assert_eq!(Shell::Nu.export_line("K", VALUE).unwrap(),
+                   "$env.K = r##'it's $HOME\\n\n'#'##");
+    }
+
+    #[test]
+    fn test_hostile_key() {
+        for shell in &[Shell::Bash, Shell::Zsh, Shell::Fish, Shell::PowerShell, Shell::Nu] {
+            for key in &["A;rm -rf ~", "$(id)", "A B", "1A", "A-B", ""] {
+                match shell.export_line(key, "v") {
+                    Err(Error::UnsupportedName(ref k, _)) => assert_eq!(k, key),
+                    other => panic!("unexpected result: {:?}", other),
+                }
+                assert!(shell.unset_line(key).is_err());
+            }
+        }
+        assert!(Shell::Bash.export_line("_A1", "v").is_ok());
+    }
+}
@@ -0,0 +1,170 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 Siphilia
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::io;
+use std::process::{Command, ExitStatus};
+
+/// 子プロセスを起動して終了を待つ。
+/// Unixでは待っている間にenvarsが受け取ったシグナルを子プロセスに転送する。
+#[cfg(unix)]
+pub fn spawn_and_wait(cmd: &mut Command) -> io::Result<ExitStatus> {
+    unix::spawn_and_wait(cmd)
+}
+
+/// 子プロセスを起動して終了を待つ。
+#[cfg(not(unix))]
+pub fn spawn_and_wait(cmd: &mut Command) -> io::Result<ExitStatus> {
+    cmd.spawn().and_then(|mut handle| handle.wait())
+}
+
+#[cfg(unix)]
+mod unix {
+    use libc;
+    use libc::c_int;
+    use std::io;
+    use std::mem;
+    use std::process::{Command, ExitStatus};
+    use std::ptr;
+    use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicUsize, Ordering};
+
+    const FORWARDED_SIGNALS: [c_int; 6] = [libc::SIGINT,
+                                           libc::SIGTERM,
+                                           libc::SIGHUP,
+                                           libc::SIGQUIT,
+                                           libc::SIGUSR1,
+                                           libc::SIGUSR2];
+
+    /// 端末で入力されたシグナル。envarsが前面のジョブの時は子プロセスも直接受け取るので転送しない。
+    const TERMINAL_SIGNALS: [c_int; 2] = [libc::SIGINT, libc::SIGQUIT];
+
+    /// 転送先の子プロセスのPID。子プロセスが無い間は0。
+    static CHILD_PID: AtomicIsize = AtomicIsize::new(0);
+    static FORWARD_TERMINAL_SIGNALS: AtomicBool = AtomicBool::new(true);
+    /// 子プロセスの起動前に受け取ったシグナルのビット集合。起動後に子プロセスへ送る。
+    static PENDING_SIGNALS: AtomicUsize = AtomicUsize::new(0);
+
+    pub fn spawn_and_wait(cmd: &mut Command) -> io::Result<ExitStatus> {
+        FORWARD_TERMINAL_SIGNALS.store(!is_foreground_job(), Ordering::SeqCst);
+        PENDING_SIGNALS.store(0, Ordering::SeqCst);
+        let previous: Vec<(c_int, libc::sigaction)> = try!(install_handlers());
+
+        let result = cmd.spawn().and_then(|mut handle| {
+            let pid = handle.id() as libc::pid_t;
+            CHILD_PID.store(pid as isize, Ordering::SeqCst);
+            // 前面のジョブの時は、起動中に端末から送られたシグナルを子プロセスも直接受け取っている
+            // ことがあり、送ると二重になるので捨てる。
+            let forward_terminal_signals = FORWARD_TERMINAL_SIGNALS.load(Ordering::SeqCst);
+            for signal in take_pending_signals() {
+                if TERMINAL_SIGNALS.contains(&signal) && !forward_terminal_signals {
+                    continue;
+                }
+                unsafe {
+                    libc::kill(pid, signal);
+                }
+            }
+            handle.wait()
+        });
+
+        CHILD_PID.store(0, Ordering::SeqCst);
+        restore_handlers(previous);
+        // 起動に失敗した場合は、受け取ったシグナルを元のハンドラで処理させる。
+        for signal in take_pending_signals() {
+            unsafe {
+                libc::raise(signal);
+            }
+        }
+        result
+    }
+
+    extern "C" fn forward(signal: c_int) {
+        let pid = CHILD_PID.load(Ordering::SeqCst);
+        if pid <= 0 {
+            PENDING_SIGNALS.fetch_or(1 << signal, Ordering::SeqCst);
+            return;
+        }
+        if TERMINAL_SIGNALS.contains(&signal) && !FORWARD_TERMINAL_SIGNALS.load(Ordering::SeqCst) {
+            return;
+        }
+        unsafe {
+            libc::kill(pid as libc::pid_t, signal);
+        }
+    }
+
+    fn take_pending_signals() -> Vec<c_int> {
+        let pending = PENDING_SIGNALS.swap(0, Ordering::SeqCst);
+        FORWARDED_SIGNALS.iter().cloned().filter(|&signal| pending & (1 << signal) != 0).collect()
+    }
+
+    /// 転送用のハンドラを設定し、元のハンドラを返す。
+    fn install_handlers() -> io::Result<Vec<(c_int, libc::sigaction)>> {
+        let mut previous = Vec::new();
+        for &signal in &FORWARDED_SIGNALS {
+            let mut action: libc::sigaction = unsafe { mem::zeroed() };
+            action.sa_sigaction = forward as extern "C" fn(c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            let mut old: libc::sigaction = unsafe { mem::zeroed() };
+            let ret = unsafe {
+                libc::sigemptyset(&mut action.sa_mask);
+                libc::sigaction(signal, &action, &mut old)
+            };
+            if ret != 0 {
+                let error = io::Error::last_os_error();
+                restore_handlers(previous);
+                return Err(error);
+            }
+            previous.push((signal, old));
+        }
+        Ok(previous)
+    }
+
+    fn restore_handlers(previous: Vec<(c_int, libc::sigaction)>) {
+        for (signal, action) in previous {
+            unsafe {
+                libc::sigaction(signal, &action, ptr::null_mut());
+            }
+        }
+    }
+
+    /// envarsが制御端末の前面のプロセスグループに属しているかを返す。
+    fn is_foreground_job() -> bool {
+        unsafe {
+            libc::isatty(libc::STDIN_FILENO) == 1 &&
+            libc::tcgetpgrp(libc::STDIN_FILENO) == libc::getpgrp()
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::spawn_and_wait;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::Command;
+
+    #[test]
+    fn test_spawn_and_wait_exit_status() {
+        let status = spawn_and_wait(Command::new("sh").args(&["-c", "exit 3"])).unwrap();
+        assert_eq!(status.code(), Some(3));
+        let status = spawn_and_wait(Command::new("sh").args(&["-c", "kill -TERM $$"])).unwrap();
+        assert_eq!(status.signal(), Some(15));
+        assert!(spawn_and_wait(&mut Command::new("envars-no-such-command")).is_err());
+    }
+}
@@ -0,0 +1,483 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2016 Siphilia
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! `EnvSet`ファイルの保存形式。
+//!
+//! 形式はファイルの拡張子で選ばれる。どの形式も、最上位の対応表を形式に依らない`Table`として読み書きする。
+
+use error::{Error, Result};
+use serde::de::{Deserializer, IgnoredAny, MapAccess, Visitor};
+use serde_json;
+use serde_json::Value as Json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error as StdError;
+use std::fmt;
+use std::path::Path;
+use std::result;
+use toml;
+use yaml_rust as yaml;
+use yaml_rust::Yaml;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+
+/// 保存形式に依らない値の表現。
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    /// 浮動小数点数は書かれた通りの文字列で保持する。
+    Float(String),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+    Null,
+}
+
+pub type Table = BTreeMap<String, Value>;
+
+/// `Storage::load`が失敗した理由。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LoadError {
+    /// 構文の誤り。行と列は1から数える。
+    Syntax(usize, usize, String),
+    /// 最上位が対応表ではない。
+    NotMapping,
+    /// 構文は正しいが`Table`として表せない内容がある。
+    Unsupported(String),
+}
+
+/// `EnvSet`ファイルの保存形式。
+pub trait Storage {
+    /// この形式のファイルの拡張子。
+    fn extension(&self) -> &'static str;
+
+    /// 内容が空の場合は空の`Table`となる。
+    fn load(&self, content: &str) -> result::Result<Table, LoadError>;
+
+    fn dump(&self, table: &Table) -> Result<String>;
+
+    /// 最上位の対応表で重複しているキーを返す。
+    /// 重複を構文の誤りとする形式や、検出できない形式では常に空となる。
+    fn duplicate_keys(&self, _content: &str) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+pub struct YamlStorage;
+pub struct TomlStorage;
+pub struct JsonStorage;
+
+/// 対応している保存形式。同じ名前の`EnvSet`ファイルが複数ある場合は先にある形式が優先される。
+pub const STORAGES: &'static [&'static (Storage + Sync)] = &[&YamlStorage,
+                                                              &TomlStorage,
+                                                              &JsonStorage];
+
+pub fn from_extension(extension: &str) -> Option<&'static Storage> {
+    STORAGES.iter()
+        .find(|storage| storage.extension() == extension)
+        .map(|storage| *storage as &Storage)
+}
+
+pub fn from_path<P: AsRef<Path>>(path: P) -> Option<&'static Storage> {
+    path.as_ref().extension().and_then(|ext| ext.to_str()).and_then(from_extension)
+}
+
+impl Storage for YamlStorage {
+    fn extension(&self) -> &'static str {
+        "yaml"
+    }
+
+    fn load(&self, content: &str) -> result::Result<Table, LoadError> {
+        let root: Vec<Yaml> = try!(yaml::YamlLoader::load_from_str(content).map_err(|e| {
+            LoadError::Syntax(e.marker().line(), e.marker().col() + 1, e.description().to_owned())
+        }));
+        match root.first() {
+            Some(&Yaml::Hash(ref hash)) => yaml_hash_to_table(hash).map_err(LoadError::Unsupported),
+            Some(&Yaml::Null) | None => Ok(Table::new()),
+            Some(_) => Err(LoadError::NotMapping),
+        }
+    }
+
+    fn dump(&self, table: &Table) -> Result<String> {
+        let mut buf = String::new();
+        {
+            let mut emitter = yaml::YamlEmitter::new(&mut buf);
+            try!(emitter.dump(&Yaml::Hash(table_to_yaml_hash(table))).map_err(Error::EmitYaml));
+        }
+        Ok(buf)
+    }
+
+    fn duplicate_keys(&self, content: &str) -> Vec<String> {
+        let mut receiver = YamlKeyCollector {
+            depth: 0,
+            is_mapping: false,
+            expect_key: false,
+            done: false,
+            keys: Vec::new(),
+        };
+        let _ = Parser::new(content.chars()).load(&mut receiver, false);
+        duplicates(receiver.keys)
+    }
+}
+
+/// 最初の文書の最上位の対応表のキーを、出現順に集める。
+struct YamlKeyCollector {
+    depth: usize,
+    is_mapping: bool,
+    /// 次に最上位の対応表に現れるノードがキーであるか。
+    expect_key: bool,
+    done: bool,
+    keys: Vec<String>,
+}
+
+impl MarkedEventReceiver for YamlKeyCollector {
+    fn on_event(&mut self, ev: Event, _mark: Marker) {
+        if self.done {
+            return;
+        }
+        let is_top_level_node = self.depth == 1 && self.is_mapping;
+        match ev {
+            Event::MappingStart(_) | Event::SequenceStart(_) => {
+                if self.depth == 0 {
+                    self.is_mapping = if let Event::MappingStart(_) = ev { true } else { false };
+                    self.expect_key = true;
+                } else if is_top_level_node {
+                    self.expect_key = !self.expect_key;
+                }
+                self.depth += 1;
+            }
+            Event::MappingEnd | Event::SequenceEnd => self.depth -= 1,
+            Event::Scalar(ref value, _, _, _) if is_top_level_node => {
+                if self.expect_key {
+                    self.keys.push(value.clone());
+                }
+                self.expect_key = !self.expect_key;
+            }
+            Event::Alias(_) if is_top_level_node => self.expect_key = !self.expect_key,
+            Event::DocumentEnd => self.done = true,
+            _ => {}
+        }
+    }
+}
+
+/// 二回以上現れる要素を、最初に重複した順に返す。
+fn duplicates(keys: Vec<String>) -> Vec<String> {
+    let mut seen = BTreeSet::new();
+    let mut duplicated = Vec::new();
+    for key in keys {
+        if !seen.insert(key.clone()) && !duplicated.contains(&key) {
+            duplicated.push(key);
+        }
+    }
+    duplicated
+}
+
+impl Storage for TomlStorage {
+    fn extension(&self) -> &'static str {
+        "toml"
+    }
+
+    fn load(&self, content: &str) -> result::Result<Table, LoadError> {
+        match content.parse::<toml::Table>() {
+            Ok(table) => Ok(table.into_iter().map(|(k, v)| (k, toml_to_value(v))).collect()),
+            Err(error) => {
+                let (line, col) = line_col(content, error.span().map_or(0, |span| span.start));
+                Err(LoadError::Syntax(line, col, error.message().to_owned()))
+            }
+        }
+    }
+
+    /// TOMLには`null`が無いので、取り除く変数は書き出せない。
+    fn dump(&self, table: &Table) -> Result<String> {
+        let mut toml_table = toml::Table::new();
+        for (k, v) in table {
+            match value_to_toml(v) {
+                Some(v) => toml_table.insert(k.clone(), v),
+                None => {
+                    let message = "toml can't express null".to_owned();
+                    return Err(Error::UnsupportedValue(k.clone(), message));
+                }
+            };
+        }
+        Ok(try!(toml::to_string(&toml_table)))
+    }
+}
+
+/// `content`の`offset`バイト目の行と列を、1から数えて返す。列は文字単位で数える。
+fn line_col(content: &str, offset: usize) -> (usize, usize) {
+    let before: &str = &content[..offset];
+    let line_start: usize = before.rfind('\n').map_or(0, |pos| pos + 1);
+    (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+}
+
+impl Storage for JsonStorage {
+    fn extension(&self) -> &'static str {
+        "json"
+    }
+
+    fn load(&self, content: &str) -> result::Result<Table, LoadError> {
+        if content.trim().is_empty() {
+            return Ok(Table::new());
+        }
+        match serde_json::from_str(content) {
+            Ok(Json::Object(object)) => {
+                Ok(object.into_iter().map(|(k, v)| (k, json_to_value(v))).collect())
+            }
+            Ok(_) => Err(LoadError::NotMapping),
+            Err(error) => {
+                // メッセージの末尾の位置は別に表示するので取り除く。
+                let message: String = error.to_string();
+                let message: &str = message.rfind(" at line ")
+                                        .map_or(&message, |pos| &message[..pos]);
+                Err(LoadError::Syntax(error.line().max(1),
+                                      error.column().max(1),
+                                      message.to_owned()))
+            }
+        }
+    }
+
+    fn dump(&self, table: &Table) -> Result<String> {
+        let object = table.iter().map(|(k, v)| (k.clone(), value_to_json(v))).collect();
+        Ok(format!("{:#}\n", Json::Object(object)))
+    }
+
+    fn duplicate_keys(&self, content: &str) -> Vec<String> {
+        let mut deserializer = serde_json::Deserializer::from_str(content);
+        duplicates((&mut deserializer).deserialize_map(JsonKeyCollector).unwrap_or_default())
+    }
+}
+
+/// 最上位のオブジェクトのキーを、値を読み飛ばしながら出現順に集める。
+struct JsonKeyCollector;
+
+impl<'de> Visitor<'de> for JsonKeyCollector {
+    type Value = Vec<String>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an object")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> result::Result<Vec<String>, A::Error>
+        where A: MapAccess<'de>
+    {
+        let mut keys = Vec::new();
+        while let Some(key) = try!(map.next_key::<String>()) {
+            try!(map.next_value::<IgnoredAny>());
+            keys.push(key);
+        }
+        Ok(keys)
+    }
+}
+
+/// 文字列以外のキーや、値として表せない要素がある場合はその説明をエラーとする。
+fn yaml_hash_to_table(hash: &yaml::yaml::Hash) -> result::Result<Table, String> {
+    let mut table = Table::new();
+    for (k, v) in hash {
+        let key: &str = try!(k.as_str().ok_or_else(|| {
+            format!("key {} is not a string", describe_yaml(k))
+        }));
+        let value: Value = try!(yaml_to_value(v)
+                                    .map_err(|message| format!("{}: {}", key, message)));
+        table.insert(key.to_owned(), value);
+    }
+    Ok(table)
+}
+
+fn yaml_to_value(yaml: &Yaml) -> result::Result<Value, String> {
+    match *yaml {
+        Yaml::String(ref s) => Ok(Value::String(s.clone())),
+        Yaml::Integer(i) => Ok(Value::Integer(i)),
+        Yaml::Real(ref s) => Ok(Value::Float(s.clone())),
+        Yaml::Boolean(b) => Ok(Value::Boolean(b)),
+        Yaml::Array(ref array) => {
+            array.iter().map(yaml_to_value).collect::<result::Result<_, _>>().map(Value::Array)
+        }
+        Yaml::Hash(ref hash) => yaml_hash_to_table(hash).map(Value::Table),
+        Yaml::Null => Ok(Value::Null),
+        Yaml::Alias(_) => Err("unresolved alias".to_owned()),
+        Yaml::BadValue => Err("invalid value".to_owned()),
+    }
+}
+
+/// エラーメッセージのために、文字列以外のキーを書かれた形に近い文字列にする。
+fn describe_yaml(yaml: &Yaml) -> String {
+    match *yaml {
+        Yaml::String(ref s) | Yaml::Real(ref s) => s.clone(),
+        Yaml::Integer(i) => i.to_string(),
+        Yaml::Boolean(b) => b.to_string(),
+        Yaml::Null => "~".to_owned(),
+        Yaml::Array(_) => "(a sequence)".to_owned(),
+        Yaml::Hash(_) => "(a mapping)".to_owned(),
+        Yaml::Alias(_) | Yaml::BadValue => "(an invalid value)".to_owned(),
+    }
+}
+
+fn table_to_yaml_hash(table: &Table) -> yaml::yaml::Hash {
+    table.iter().map(|(k, v)| (Yaml::String(k.clone()), value_to_yaml(v))).collect()
+}
+
+fn value_to_yaml(value: &Value) -> Yaml {
+    match *value {
+        Value::String(ref s) => Yaml::String(s.clone()),
+        Value::Integer(i) => Yaml::Integer(i),
+        Value::Float(ref s) => Yaml::Real(s.clone()),
+        Value::Boolean(b) => Yaml::Boolean(b),
+        Value::Array(ref array) => Yaml::Array(array.iter().map(value_to_yaml).collect()),
+        Value::Table(ref table) => Yaml::Hash(table_to_yaml_hash(table)),
+        Value::Null => Yaml::Null,
+    }
+}
+
+/// 日時は書かれた通りの文字列として扱う。
+fn toml_to_value(toml: toml::Value) -> Value {
+    match toml {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Datetime(datetime) => Value::String(datetime.to_string()),
+        toml::Value::Integer(i) => Value::Integer(i),
+        toml::Value::Float(f) => Value::Float(f.to_string()),
+        toml::Value::Boolean(b) => Value::Boolean(b),
+        toml::Value::Array(array) => Value::Array(array.into_iter().map(toml_to_value).collect()),
+        toml::Value::Table(table) => {
+            Value::Table(table.into_iter().map(|(k, v)| (k, toml_to_value(v))).collect())
+        }
+    }
+}
+
+fn value_to_toml(value: &Value) -> Option<toml::Value> {
+    match *value {
+        Value::String(ref s) => Some(toml::Value::String(s.clone())),
+        Value::Integer(i) => Some(toml::Value::Integer(i)),
+        Value::Float(ref s) => s.parse().ok().map(toml::Value::Float),
+        Value::Boolean(b) => Some(toml::Value::Boolean(b)),
+        Value::Array(ref array) => {
+            array.iter().map(value_to_toml).collect::<Option<_>>().map(toml::Value::Array)
+        }
+        Value::Table(ref table) => {
+            table.iter()
+                 .map(|(k, v)| value_to_toml(v).map(|v| (k.clone(), v)))
+                 .collect::<Option<_>>()
+                 .map(toml::Value::Table)
+        }
+        Value::Null => None,
+    }
+}
+
+/// 整数として表せない数は書かれた通りの文字列で保持する。
+fn json_to_value(json: Json) -> Value {
+    match json {
+        Json::String(s) => Value::String(s),
+        Json::Number(n) => n.as_i64().map_or_else(|| Value::Float(n.to_string()), Value::Integer),
+        Json::Bool(b) => Value::Boolean(b),
+        Json::Array(array) => Value::Array(array.into_iter().map(json_to_value).collect()),
+        Json::Object(object) => {
+            Value::Table(object.into_iter().map(|(k, v)| (k, json_to_value(v))).collect())
+        }
+        Json::Null => Value::Null,
+    }
+}
+
+fn value_to_json(value: &Value) -> Json {
+    match *value {
+        Value::String(ref s) => Json::String(s.clone()),
+        Value::Integer(i) => Json::from(i),
+        Value::Float(ref s) => {
+            s.parse()
+             .ok()
+             .and_then(serde_json::Number::from_f64)
+             .map_or_else(|| Json::String(s.clone()), Json::Number)
+        }
+        Value::Boolean(b) => Json::Bool(b),
+        Value::Array(ref array) => Json::Array(array.iter().map(value_to_json).collect()),
+        Value::Table(ref table) => {
+            Json::Object(table.iter().map(|(k, v)| (k.clone(), value_to_json(v))).collect())
+        }
+        Value::Null => Json::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JsonStorage, LoadError, STORAGES, Storage, Table, TomlStorage, Value, YamlStorage,
+                yaml_to_value};
+    use error::Error;
+    use yaml_rust::Yaml;
+
+    fn table(with_null: bool) -> Table {
+        let mut table = Table::new();
+        table.insert("extends".to_owned(),
+                     Value::Array(vec![Value::String("base".to_owned()),
+                                       Value::String("db".to_owned())]));
+        table.insert("QUOTED".to_owned(),
+                     Value::String("it's \"quoted\"\n\t# not a comment".to_owned()));
+        table.insert("EMPTY".to_owned(), Value::String(String::new()));
+        table.insert("NUMBER_LIKE".to_owned(), Value::String("0123".to_owned()));
+        table.insert("PORT".to_owned(), Value::Integer(5432));
+        table.insert("RATIO".to_owned(), Value::Float("1.5".to_owned()));
+        table.insert("DEBUG".to_owned(), Value::Boolean(true));
+        if with_null {
+            table.insert("REMOVED".to_owned(), Value::Null);
+        }
+        table
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for storage in &[&YamlStorage as &Storage, &JsonStorage] {
+            let orig = table(true);
+            let dumped = storage.dump(&orig).unwrap();
+            assert_eq!(storage.load(&dumped), Ok(orig), "{}", storage.extension());
+        }
+        let orig = table(false);
+        let dumped = TomlStorage.dump(&orig).unwrap();
+        assert_eq!(TomlStorage.load(&dumped), Ok(orig));
+    }
+
+    #[test]
+    fn test_toml_null() {
+        match TomlStorage.dump(&table(true)) {
+            Err(Error::UnsupportedValue(ref key, _)) => assert_eq!(key, "REMOVED"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_load_empty() {
+        for storage in STORAGES {
+            assert_eq!(storage.load(""), Ok(Table::new()), "{}", storage.extension());
+        }
+    }
+
+    #[test]
+    fn test_yaml_unsupported() {
+        assert_eq!(YamlStorage.load("A: 1\n1: one\n"),
+                   Err(LoadError::Unsupported("key 1 is not a string".to_owned())));
+        assert_eq!(YamlStorage.load("A:\n  ? [x]\n  : y\n"),
+                   Err(LoadError::Unsupported("A: key (a sequence) is not a string".to_owned())));
+        assert!(yaml_to_value(&Yaml::Alias(0)).is_err());
+        assert!(yaml_to_value(&Yaml::Array(vec![Yaml::BadValue])).is_err());
+
+        let mut expected = Table::new();
+        expected.insert("A".to_owned(), Value::String("x".to_owned()));
+        expected.insert("B".to_owned(), Value::String("x".to_owned()));
+        assert_eq!(YamlStorage.load("A: &a x\nB: *a\n"), Ok(expected));
+    }
+}